@@ -0,0 +1,900 @@
+use encoding_rs::Encoding;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use {BinaryMode, ColorChoice, Config, IndexMode, MatcherKind, Normalization, OutputMode, Sort};
+use STDIN;
+
+pub const USAGE: &str = "\
+Usage: minigrep_v2 [OPTIONS] QUERY [PATH]...
+       minigrep_v2 [OPTIONS] -e QUERY... [PATH]...
+       minigrep_v2 [OPTIONS] -f FILE... [PATH]...
+       minigrep_v2 index [DIR]...
+
+Searches each PATH (recursing into directories) for lines containing QUERY.
+With no PATH, or when PATH is -, reads standard input.
+
+`minigrep_v2 index` builds an index of the files in each DIR (by default
+the current one), or brings an existing one up to date, for --index to
+skip files with. To search for `index` itself, use -e index.
+
+Options:
+  -e, --regexp QUERY           search for QUERY; may be given more than once
+  -f, --file FILE              search for each line of FILE as a QUERY
+  -i, --ignore-case            match case-insensitively
+  -E, --regex                  treat QUERY as a regular expression
+  -w, --word-regexp            only match whole words
+  -x, --line-regexp            only match whole lines
+      --fuzzy K                match QUERY with up to K characters inserted,
+                               deleted or substituted (--json shows how many)
+  -U, --multiline              let matches span lines, printing every line
+                               each one covers
+      --case-fold              match using full Unicode case folding, also
+                               treating curly quotes as straight ones
+      --normalize FORM         compare text in normalization form nfc or nfd
+  -v, --invert-match           select lines that don't match
+  -a, --text                   search binary files as if they were text
+  -z, --search-zip             search the contents of gzipped files
+      --archives               search each file in tar archives, printing
+                               hits as ARCHIVE:MEMBER:LINE
+      --encoding LABEL         decode files without a byte order mark as
+                               LABEL (latin1, windows-1252, utf-16le, ...)
+      --binary-skip            don't search binary files at all
+  -n, --line-number            prefix lines with their line number
+  -b, --byte-offset            prefix lines with their byte offset
+  -A, --after-context NUM      print NUM lines after each match
+  -B, --before-context NUM     print NUM lines before each match
+  -C, --context NUM            print NUM lines around each match
+  -c, --count                  print only a count of selected lines per file
+  -l, --files-with-matches     print only names of files with a match
+  -L, --files-without-match    print only names of files without a match
+      --json                   print results as JSON Lines
+      --watch                  keep following each PATH like `tail -F`,
+                               printing new matches as lines are added
+  -r, --replace TEXT           print a diff of replacing each match with TEXT,
+                               in which $1 or ${name} stand for regex groups
+      --in-place               write the replacements back to the files
+      --color WHEN             highlight output: auto, always or never
+      --follow                 follow symlinks while recursing
+      --max-depth NUM          descend at most NUM directories
+      --hidden                 search hidden files and directories
+      --no-ignore              don't respect .gitignore and .ignore files
+  -g, --glob GLOB              only search files matching GLOB, or skip
+                               those matching !GLOB; may be repeated
+  -j, --threads NUM            search NUM files at once (default: one per CPU)
+      --sort ORDER             print files in ORDER: none (as they finish) or
+                               path (in the order they were given and found)
+      --index                  skip files that the index of a directory PATH
+                               shows can't match
+  -h, --help                   print this help and exit
+  -V, --version                print version information and exit
+
+Setting CASE_INSENSITIVE in the environment has the same effect as -i.";
+
+/// Why a list of arguments couldn't be turned into a `Config`.
+///
+/// `Help` and `Version` aren't failures as such, but like them they mean
+/// there's nothing to search.
+#[derive(Debug, PartialEq)]
+pub enum ArgsError {
+    Help,
+    Version,
+    UnknownOption(String),
+    MissingValue(String),
+    UnexpectedValue(String),
+    InvalidValue {
+        option: String,
+        value: String,
+        expected: &'static str,
+    },
+    PatternFile {
+        path: String,
+        reason: String,
+    },
+    /// An option was given without another one it only makes sense with
+    Requires {
+        option: &'static str,
+        requires: &'static str,
+    },
+    /// Two options were given that can't be used together
+    Conflicts {
+        option: &'static str,
+        with: &'static str,
+    },
+    MissingQuery,
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ArgsError::Help => write!(f, "{}", USAGE),
+            ArgsError::Version => write!(f, "minigrep_v2 {}", env!("CARGO_PKG_VERSION")),
+            ArgsError::UnknownOption(ref opt) => write!(f, "unknown option `{}`", opt),
+            ArgsError::MissingValue(ref opt) => write!(f, "option `{}` requires a value", opt),
+            ArgsError::UnexpectedValue(ref opt) => {
+                write!(f, "option `{}` doesn't take a value", opt)
+            }
+            ArgsError::InvalidValue {
+                ref option,
+                ref value,
+                expected,
+            } => write!(
+                f,
+                "invalid value `{}` for `{}`: expected {}",
+                value, option, expected
+            ),
+            ArgsError::PatternFile { ref path, ref reason } => {
+                write!(f, "couldn't read patterns from `{}`: {}", path, reason)
+            }
+            ArgsError::Requires { option, requires } => {
+                write!(f, "option `{}` requires `{}`", option, requires)
+            }
+            ArgsError::Conflicts { option, with } => {
+                write!(f, "option `{}` can't be used with `{}`", option, with)
+            }
+            ArgsError::MissingQuery => write!(f, "no query string provided"),
+        }
+    }
+}
+
+impl Error for ArgsError {}
+
+struct Opt {
+    short: Option<char>,
+    long: &'static str,
+    takes_value: bool,
+}
+
+const fn flag(short: Option<char>, long: &'static str) -> Opt {
+    Opt {
+        short,
+        long,
+        takes_value: false,
+    }
+}
+
+const fn valued(short: Option<char>, long: &'static str) -> Opt {
+    Opt {
+        short,
+        long,
+        takes_value: true,
+    }
+}
+
+const OPTS: &[Opt] = &[
+    valued(Some('e'), "regexp"),
+    valued(Some('f'), "file"),
+    flag(Some('i'), "ignore-case"),
+    flag(Some('E'), "regex"),
+    flag(Some('w'), "word-regexp"),
+    flag(Some('x'), "line-regexp"),
+    valued(None, "fuzzy"),
+    flag(Some('U'), "multiline"),
+    flag(None, "case-fold"),
+    valued(None, "normalize"),
+    flag(Some('v'), "invert-match"),
+    flag(Some('a'), "text"),
+    flag(Some('z'), "search-zip"),
+    flag(None, "archives"),
+    flag(None, "binary-skip"),
+    valued(None, "encoding"),
+    flag(Some('n'), "line-number"),
+    flag(Some('b'), "byte-offset"),
+    valued(Some('A'), "after-context"),
+    valued(Some('B'), "before-context"),
+    valued(Some('C'), "context"),
+    flag(Some('c'), "count"),
+    flag(Some('l'), "files-with-matches"),
+    flag(Some('L'), "files-without-match"),
+    flag(None, "json"),
+    flag(None, "watch"),
+    valued(Some('r'), "replace"),
+    flag(None, "in-place"),
+    valued(None, "color"),
+    valued(None, "colour"),
+    flag(None, "follow"),
+    valued(None, "max-depth"),
+    flag(None, "hidden"),
+    flag(None, "no-ignore"),
+    valued(Some('g'), "glob"),
+    valued(Some('j'), "threads"),
+    valued(None, "sort"),
+    flag(None, "index"),
+    flag(Some('h'), "help"),
+    flag(Some('V'), "version"),
+];
+
+impl Opt {
+    // whether this option supplies patterns, so the query isn't positional
+    fn gives_patterns(&self) -> bool {
+        self.long == "regexp" || self.long == "file"
+    }
+}
+
+fn read_pattern_file(path: &str) -> io::Result<String> {
+    if path == STDIN {
+        let mut contents = String::new();
+        io::stdin().read_to_string(&mut contents)?;
+        Ok(contents)
+    } else {
+        fs::read_to_string(path)
+    }
+}
+
+impl Config {
+    /// Builds a `Config` from command-line arguments, the first of which is
+    /// taken to be the program name and skipped, as with `env::args()`.
+    /// Case-insensitivity can also be turned on with `CASE_INSENSITIVE`.
+    pub fn new<I>(args: I) -> Result<Self, ArgsError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut cfg = Config::parse(args.into_iter().skip(1))?;
+        if env::var("CASE_INSENSITIVE").is_ok() {
+            cfg.case_sensitive = false;
+        }
+        Ok(cfg)
+    }
+
+    /// Builds a `Config` from arguments alone, without a program name and
+    /// without consulting the environment.
+    ///
+    /// Short flags may be bundled (`-inv`) and take values either attached
+    /// or as the next argument (`-A2`, `-A 2`); long options take them as
+    /// `--name=value` or `--name value`. Everything after `--` is positional.
+    ///
+    /// Unless patterns were given with `-e` or `-f`, the first positional
+    /// argument is the query. Pattern files are read straight away.
+    pub fn parse<I>(args: I) -> Result<Self, ArgsError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut cfg = Config {
+            color: ColorChoice::Auto,
+            ..Config::default()
+        };
+        let mut positional = Vec::new();
+        let mut patterns_given = false;
+        let mut args = args.into_iter().peekable();
+        if args.peek().is_some_and(|arg| arg == "index") {
+            args.next();
+            return Config::parse_index(args);
+        }
+
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                positional.extend(&mut args);
+            } else if let Some(long) = arg.strip_prefix("--") {
+                let (name, inline) = match long.find('=') {
+                    Some(i) => (&long[..i], Some(long[i + 1..].to_string())),
+                    None => (long, None),
+                };
+                let opt = OPTS
+                    .iter()
+                    .find(|o| o.long == name)
+                    .ok_or_else(|| ArgsError::UnknownOption(format!("--{}", name)))?;
+                let value = match (opt.takes_value, inline) {
+                    (true, Some(value)) => Some(value),
+                    (true, None) => Some(
+                        args.next()
+                            .ok_or_else(|| ArgsError::MissingValue(format!("--{}", name)))?,
+                    ),
+                    (false, Some(_)) => {
+                        return Err(ArgsError::UnexpectedValue(format!("--{}", name)))
+                    }
+                    (false, None) => None,
+                };
+                cfg.apply(opt, &format!("--{}", name), value)?;
+                patterns_given |= opt.gives_patterns();
+            } else if arg.len() > 1 && arg.starts_with('-') {
+                let shorts = &arg[1..];
+                for (i, c) in shorts.char_indices() {
+                    let opt = OPTS
+                        .iter()
+                        .find(|o| o.short == Some(c))
+                        .ok_or_else(|| ArgsError::UnknownOption(format!("-{}", c)))?;
+                    let name = format!("-{}", c);
+                    if !opt.takes_value {
+                        cfg.apply(opt, &name, None)?;
+                        continue;
+                    }
+                    // the rest of the bundle, if any, is this option's value
+                    let rest = &shorts[i + c.len_utf8()..];
+                    let value = if rest.is_empty() {
+                        args.next()
+                            .ok_or_else(|| ArgsError::MissingValue(name.clone()))?
+                    } else {
+                        rest.to_string()
+                    };
+                    cfg.apply(opt, &name, Some(value))?;
+                    patterns_given |= opt.gives_patterns();
+                    break;
+                }
+            } else {
+                positional.push(arg);
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        if !patterns_given {
+            cfg.patterns.push(positional.next().ok_or(ArgsError::MissingQuery)?);
+        }
+        cfg.paths = positional.collect();
+        if cfg.paths.is_empty() {
+            cfg.paths.push(String::from(STDIN));
+        }
+        if cfg.in_place && cfg.replace.is_none() {
+            return Err(ArgsError::Requires {
+                option: "--in-place",
+                requires: "--replace",
+            });
+        }
+        if cfg.fuzzy.is_some() {
+            let conflict = if cfg.matcher == MatcherKind::Regex {
+                Some("--regex")
+            } else if cfg.word_regexp {
+                Some("--word-regexp")
+            } else if cfg.line_regexp {
+                Some("--line-regexp")
+            } else {
+                None
+            };
+            if let Some(with) = conflict {
+                return Err(ArgsError::Conflicts {
+                    option: "--fuzzy",
+                    with,
+                });
+            }
+        }
+        if cfg.watch {
+            // there's no end of the files to report on or rewrite them at,
+            // and they're followed as plain text a line at a time
+            let conflict = match cfg.output {
+                _ if cfg.replace.is_some() => Some("--replace"),
+                _ if cfg.multiline => Some("--multiline"),
+                _ if cfg.search_zip => Some("--search-zip"),
+                _ if cfg.archives => Some("--archives"),
+                _ if cfg.index == IndexMode::Use => Some("--index"),
+                OutputMode::Count => Some("--count"),
+                OutputMode::FilesWithMatches => Some("--files-with-matches"),
+                OutputMode::FilesWithoutMatch => Some("--files-without-match"),
+                _ => None,
+            };
+            if let Some(with) = conflict {
+                return Err(ArgsError::Conflicts {
+                    option: "--watch",
+                    with,
+                });
+            }
+        }
+        if cfg.replace.is_some() {
+            // replacing works on matches, and shows or rewrites every line
+            let conflict = match cfg.output {
+                _ if cfg.invert_match => Some("--invert-match"),
+                OutputMode::Count => Some("--count"),
+                OutputMode::FilesWithMatches => Some("--files-with-matches"),
+                OutputMode::FilesWithoutMatch => Some("--files-without-match"),
+                OutputMode::Json => Some("--json"),
+                _ => None,
+            };
+            if let Some(with) = conflict {
+                return Err(ArgsError::Conflicts {
+                    option: "--replace",
+                    with,
+                });
+            }
+        }
+        Ok(cfg)
+    }
+
+    // `index [DIR]...`, which takes no options but `--help`
+    fn parse_index<I: Iterator<Item = String>>(args: I) -> Result<Self, ArgsError> {
+        let mut cfg = Config {
+            index: IndexMode::Build,
+            ..Config::default()
+        };
+        let mut options = true;
+        for arg in args {
+            match arg.as_str() {
+                "--" if options => options = false,
+                "-h" | "--help" if options => return Err(ArgsError::Help),
+                _ if options && arg.len() > 1 && arg.starts_with('-') => {
+                    return Err(ArgsError::UnknownOption(arg))
+                }
+                _ => cfg.paths.push(arg),
+            }
+        }
+        if cfg.paths.is_empty() {
+            cfg.paths.push(String::from("."));
+        }
+        Ok(cfg)
+    }
+
+    // `name` is the option as it was spelled, for error messages
+    fn apply(&mut self, opt: &Opt, name: &str, value: Option<String>) -> Result<(), ArgsError> {
+        let value = value.unwrap_or_default();
+        let number = |expected| {
+            value.parse::<usize>().map_err(|_| ArgsError::InvalidValue {
+                option: name.to_string(),
+                value: value.clone(),
+                expected,
+            })
+        };
+        match opt.long {
+            "regexp" => self.patterns.push(value),
+            "file" => {
+                let contents = read_pattern_file(&value).map_err(|e| ArgsError::PatternFile {
+                    path: value.clone(),
+                    reason: e.to_string(),
+                })?;
+                self.patterns.extend(contents.lines().map(String::from));
+            }
+            "ignore-case" => self.case_sensitive = false,
+            "regex" => self.matcher = MatcherKind::Regex,
+            "word-regexp" => self.word_regexp = true,
+            "line-regexp" => self.line_regexp = true,
+            "fuzzy" => self.fuzzy = Some(number("a number of characters")?),
+            "multiline" => self.multiline = true,
+            "case-fold" => self.case_fold = true,
+            "normalize" => {
+                self.normalization = match value.to_lowercase().as_str() {
+                    "nfc" => Some(Normalization::Nfc),
+                    "nfd" => Some(Normalization::Nfd),
+                    _ => {
+                        return Err(ArgsError::InvalidValue {
+                            option: name.to_string(),
+                            value,
+                            expected: "nfc or nfd",
+                        })
+                    }
+                }
+            }
+            "invert-match" => self.invert_match = true,
+            "text" => self.binary = BinaryMode::Text,
+            "search-zip" => self.search_zip = true,
+            "archives" => self.archives = true,
+            "binary-skip" => self.binary = BinaryMode::Skip,
+            "encoding" => {
+                let encoding = Encoding::for_label_no_replacement(value.as_bytes());
+                self.encoding = Some(encoding.ok_or_else(|| ArgsError::InvalidValue {
+                    option: name.to_string(),
+                    value: value.clone(),
+                    expected: "an encoding label such as latin1 or utf-16le",
+                })?);
+            }
+            "line-number" => self.line_number = true,
+            "byte-offset" => self.byte_offset = true,
+            "after-context" => self.after_context = number("a number of lines")?,
+            "before-context" => self.before_context = number("a number of lines")?,
+            "context" => {
+                self.after_context = number("a number of lines")?;
+                self.before_context = self.after_context;
+            }
+            "count" => self.output = OutputMode::Count,
+            "files-with-matches" => self.output = OutputMode::FilesWithMatches,
+            "files-without-match" => self.output = OutputMode::FilesWithoutMatch,
+            "json" => self.output = OutputMode::Json,
+            "watch" => self.watch = true,
+            "replace" => self.replace = Some(value),
+            "in-place" => self.in_place = true,
+            "color" | "colour" => {
+                self.color = match value.as_str() {
+                    "auto" => ColorChoice::Auto,
+                    "always" => ColorChoice::Always,
+                    "never" => ColorChoice::Never,
+                    _ => {
+                        return Err(ArgsError::InvalidValue {
+                            option: name.to_string(),
+                            value,
+                            expected: "one of auto, always or never",
+                        })
+                    }
+                }
+            }
+            "follow" => self.follow_links = true,
+            "max-depth" => self.max_depth = Some(number("a number of directories")?),
+            "hidden" => self.hidden = true,
+            "no-ignore" => self.no_ignore = true,
+            "glob" => self.globs.push(value),
+            "threads" => self.threads = number("a number of threads")?,
+            "sort" => {
+                self.sort = match value.as_str() {
+                    "none" => Sort::Unsorted,
+                    "path" => Sort::Path,
+                    _ => {
+                        return Err(ArgsError::InvalidValue {
+                            option: name.to_string(),
+                            value,
+                            expected: "one of none or path",
+                        })
+                    }
+                }
+            }
+            "index" => self.index = IndexMode::Use,
+            "help" => return Err(ArgsError::Help),
+            "version" => return Err(ArgsError::Version),
+            _ => unreachable!("option `--{}` is listed but not handled", opt.long),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common;
+
+    fn parse(args: &[&str]) -> Result<Config, ArgsError> {
+        Config::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    fn cfg(query: &str, paths: &[&str]) -> Config {
+        Config {
+            patterns: vec![query.to_string()],
+            paths: paths.iter().map(|p| p.to_string()).collect(),
+            color: ColorChoice::Auto,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn parses_a_query_and_paths() {
+        assert_eq!(parse(&["body", "poem.txt"]), Ok(cfg("body", &["poem.txt"])));
+        assert_eq!(
+            parse(&["body", "a.txt", "b.txt"]),
+            Ok(cfg("body", &["a.txt", "b.txt"]))
+        );
+    }
+
+    #[test]
+    fn reads_standard_input_without_paths() {
+        assert_eq!(parse(&["body"]), Ok(cfg("body", &["-"])));
+        assert_eq!(parse(&["body", "-"]), Ok(cfg("body", &["-"])));
+    }
+
+    #[test]
+    fn parses_short_and_long_flags_anywhere() {
+        let expected = Config {
+            case_sensitive: false,
+            line_number: true,
+            invert_match: true,
+            follow_links: true,
+            ..cfg("body", &["poem.txt"])
+        };
+        assert_eq!(
+            parse(&["-i", "body", "--line-number", "poem.txt", "-v", "--follow"]),
+            Ok(expected)
+        );
+    }
+
+    #[test]
+    fn parses_bundled_short_flags() {
+        let expected = Config {
+            word_regexp: true,
+            line_regexp: true,
+            ..cfg("body", &["poem.txt"])
+        };
+        assert_eq!(parse(&["-wx", "body", "poem.txt"]), Ok(expected));
+        let expected = Config {
+            case_sensitive: false,
+            line_number: true,
+            byte_offset: true,
+            after_context: 3,
+            ..cfg("body", &["poem.txt"])
+        };
+        assert_eq!(parse(&["-inbA3", "body", "poem.txt"]), Ok(expected));
+        let expected = Config {
+            line_number: true,
+            before_context: 2,
+            ..cfg("body", &["poem.txt"])
+        };
+        assert_eq!(parse(&["-nB", "2", "body", "poem.txt"]), Ok(expected));
+    }
+
+    #[test]
+    fn parses_option_values_in_every_spelling() {
+        let expected = || Config {
+            before_context: 2,
+            after_context: 2,
+            max_depth: Some(4),
+            color: ColorChoice::Never,
+            ..cfg("body", &["poem.txt"])
+        };
+        assert_eq!(
+            parse(&["-C", "2", "--max-depth=4", "--color", "never", "body", "poem.txt"]),
+            Ok(expected())
+        );
+        assert_eq!(
+            parse(&["--context=2", "--max-depth", "4", "--colour=never", "body", "poem.txt"]),
+            Ok(expected())
+        );
+    }
+
+    #[test]
+    fn parses_unicode_matching_options() {
+        let expected = Config {
+            case_fold: true,
+            normalization: Some(Normalization::Nfd),
+            ..cfg("body", &["poem.txt"])
+        };
+        assert_eq!(
+            parse(&["--case-fold", "--normalize", "NFD", "body", "poem.txt"]),
+            Ok(expected)
+        );
+        let err = parse(&["--normalize=nfkc", "body"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid value `nfkc` for `--normalize`: expected nfc or nfd"
+        );
+    }
+
+    #[test]
+    fn takes_every_positional_as_a_path_once_patterns_are_given() {
+        let expected = Config {
+            patterns: vec![String::from("frog"), String::from("-bog")],
+            ..cfg("frog", &["poem.txt", "notes.txt"])
+        };
+        assert_eq!(
+            parse(&["-e", "frog", "poem.txt", "--regexp=-bog", "notes.txt"]),
+            Ok(expected)
+        );
+        assert_eq!(
+            parse(&["-efrog"]).map(|c| (c.patterns, c.paths)),
+            Ok((vec![String::from("frog")], vec![String::from("-")]))
+        );
+    }
+
+    #[test]
+    fn reads_one_pattern_per_line_of_a_pattern_file() {
+        let dir = common::fixture_dir("patterns", &[("patterns.txt", "frog\nbog\r\n\nnobody\n")]);
+        let path = dir.join("patterns.txt");
+        let path = path.to_str().unwrap();
+
+        let patterns = parse(&["-e", "toad", "-f", path, "poem.txt"]).map(|c| c.patterns);
+        assert_eq!(
+            patterns,
+            Ok(vec!["toad", "frog", "bog", "", "nobody"]
+                .into_iter()
+                .map(String::from)
+                .collect())
+        );
+        let err = parse(&["-f", "/nonexistent/patterns"]).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("couldn't read patterns from `/nonexistent/patterns`: "));
+    }
+
+    #[test]
+    fn treats_everything_after_a_double_dash_as_positional() {
+        assert_eq!(
+            parse(&["-n", "--", "-v", "--count", "-"]),
+            Ok(Config {
+                line_number: true,
+                ..cfg("-v", &["--count", "-"])
+            })
+        );
+    }
+
+    #[test]
+    fn parses_traversal_filters() {
+        let expected = Config {
+            hidden: true,
+            no_ignore: true,
+            globs: vec![String::from("*.rs"), String::from("!*.lock")],
+            ..cfg("body", &["src"])
+        };
+        assert_eq!(
+            parse(&["--hidden", "-g", "*.rs", "--no-ignore", "--glob=!*.lock", "body", "src"]),
+            Ok(expected)
+        );
+    }
+
+    #[test]
+    fn parses_encoding_labels() {
+        assert_eq!(
+            parse(&["--encoding", "latin1", "body"]).map(|c| c.encoding),
+            Ok(Some(encoding_rs::WINDOWS_1252))
+        );
+        assert_eq!(
+            parse(&["--encoding=UTF-16LE", "body"]).map(|c| c.encoding),
+            Ok(Some(encoding_rs::UTF_16LE))
+        );
+        let err = parse(&["--encoding=klingon", "body"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid value `klingon` for `--encoding`: expected an encoding label such as latin1 or utf-16le"
+        );
+    }
+
+    #[test]
+    fn parses_threads_and_sort_order() {
+        let expected = Config {
+            threads: 4,
+            sort: Sort::Path,
+            ..cfg("body", &["poem.txt"])
+        };
+        assert_eq!(parse(&["-j4", "--sort=path", "body", "poem.txt"]), Ok(expected));
+        let err = parse(&["--sort", "size", "body"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid value `size` for `--sort`: expected one of none or path"
+        );
+    }
+
+    #[test]
+    fn later_output_modes_win() {
+        assert_eq!(
+            parse(&["-c", "-l", "body"]).map(|c| c.output),
+            Ok(OutputMode::FilesWithMatches)
+        );
+        assert_eq!(
+            parse(&["-l", "--json", "body"]).map(|c| c.output),
+            Ok(OutputMode::Json)
+        );
+        assert_eq!(
+            parse(&["--binary-skip", "-a", "body"]).map(|c| c.binary),
+            Ok(BinaryMode::Text)
+        );
+    }
+
+    #[test]
+    fn parses_replacements() {
+        assert_eq!(
+            parse(&["-E", "(\\w+)@", "-r", "$1 at ", "--in-place", "a.txt"]),
+            Ok(Config {
+                matcher: MatcherKind::Regex,
+                replace: Some(String::from("$1 at ")),
+                in_place: true,
+                ..cfg("(\\w+)@", &["a.txt"])
+            })
+        );
+        assert_eq!(
+            parse(&["--in-place", "body", "a.txt"]).unwrap_err().to_string(),
+            "option `--in-place` requires `--replace`"
+        );
+        assert_eq!(
+            parse(&["-v", "-r", "X", "body", "a.txt"]).unwrap_err().to_string(),
+            "option `--replace` can't be used with `--invert-match`"
+        );
+        assert_eq!(
+            parse(&["-l", "-r", "X", "body", "a.txt"]).unwrap_err().to_string(),
+            "option `--replace` can't be used with `--files-with-matches`"
+        );
+        assert_eq!(
+            parse(&["--json", "-r", "X", "body", "a.txt"]).unwrap_err().to_string(),
+            "option `--replace` can't be used with `--json`"
+        );
+    }
+
+    #[test]
+    fn parses_fuzzy_matching() {
+        assert_eq!(
+            parse(&["--fuzzy", "1", "nobody"]),
+            Ok(Config {
+                fuzzy: Some(1),
+                ..cfg("nobody", &["-"])
+            })
+        );
+        assert_eq!(
+            parse(&["--fuzzy=2", "-w", "nobody"]).unwrap_err().to_string(),
+            "option `--fuzzy` can't be used with `--word-regexp`"
+        );
+        assert_eq!(
+            parse(&["-E", "--fuzzy=2", "nobody"]).unwrap_err().to_string(),
+            "option `--fuzzy` can't be used with `--regex`"
+        );
+    }
+
+    #[test]
+    fn parses_multiline_mode() {
+        assert_eq!(
+            parse(&["-UE", r"fn \w+\(\n", "src"]),
+            Ok(Config {
+                matcher: MatcherKind::Regex,
+                multiline: true,
+                ..cfg(r"fn \w+\(\n", &["src"])
+            })
+        );
+    }
+
+    #[test]
+    fn parses_the_index_subcommand() {
+        let index = |paths: &[&str]| Config {
+            index: IndexMode::Build,
+            paths: paths.iter().map(|p| p.to_string()).collect(),
+            ..Config::default()
+        };
+        assert_eq!(parse(&["index"]), Ok(index(&["."])));
+        assert_eq!(parse(&["index", "src", "--", "-dir"]), Ok(index(&["src", "-dir"])));
+        assert_eq!(parse(&["index", "-n"]), Err(ArgsError::UnknownOption(String::from("-n"))));
+        // only as the first argument, and otherwise a query like any other
+        assert_eq!(
+            parse(&["--index", "-n", "index", "src"]),
+            Ok(Config {
+                index: IndexMode::Use,
+                line_number: true,
+                ..cfg("index", &["src"])
+            })
+        );
+    }
+
+    #[test]
+    fn parses_watch_mode() {
+        assert_eq!(
+            parse(&["--watch", "ERROR", "app.log"]),
+            Ok(Config {
+                watch: true,
+                ..cfg("ERROR", &["app.log"])
+            })
+        );
+        assert_eq!(
+            parse(&["--watch", "-c", "ERROR", "app.log"]).unwrap_err().to_string(),
+            "option `--watch` can't be used with `--count`"
+        );
+        assert_eq!(
+            parse(&["--watch", "-U", "ERROR", "app.log"]).unwrap_err().to_string(),
+            "option `--watch` can't be used with `--multiline`"
+        );
+        assert_eq!(
+            parse(&["--watch", "-z", "ERROR", "app.log.gz"]).unwrap_err().to_string(),
+            "option `--watch` can't be used with `--search-zip`"
+        );
+    }
+
+    #[test]
+    fn stops_for_help_and_version() {
+        assert_eq!(parse(&["--help"]), Err(ArgsError::Help));
+        assert_eq!(parse(&["body", "-h", "--bogus"]), Err(ArgsError::Help));
+        assert_eq!(parse(&["-V"]), Err(ArgsError::Version));
+        assert!(ArgsError::Help.to_string().starts_with("Usage: minigrep_v2"));
+        assert_eq!(
+            ArgsError::Version.to_string(),
+            format!("minigrep_v2 {}", env!("CARGO_PKG_VERSION"))
+        );
+    }
+
+    #[test]
+    fn reports_unknown_options() {
+        let err = parse(&["--bogus", "body"]).unwrap_err();
+        assert_eq!(err.to_string(), "unknown option `--bogus`");
+        let err = parse(&["-nq", "body"]).unwrap_err();
+        assert_eq!(err.to_string(), "unknown option `-q`");
+    }
+
+    #[test]
+    fn reports_missing_and_unexpected_values() {
+        let err = parse(&["body", "-A"]).unwrap_err();
+        assert_eq!(err.to_string(), "option `-A` requires a value");
+        let err = parse(&["body", "--max-depth"]).unwrap_err();
+        assert_eq!(err.to_string(), "option `--max-depth` requires a value");
+        let err = parse(&["--count=3", "body"]).unwrap_err();
+        assert_eq!(err.to_string(), "option `--count` doesn't take a value");
+    }
+
+    #[test]
+    fn reports_invalid_values() {
+        let err = parse(&["-C", "lots", "body"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid value `lots` for `-C`: expected a number of lines"
+        );
+        let err = parse(&["--color=sometimes", "body"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid value `sometimes` for `--color`: expected one of auto, always or never"
+        );
+    }
+
+    #[test]
+    fn reports_a_missing_query() {
+        assert_eq!(parse(&[]), Err(ArgsError::MissingQuery));
+        assert_eq!(parse(&["-n", "--"]), Err(ArgsError::MissingQuery));
+        assert_eq!(
+            ArgsError::MissingQuery.to_string(),
+            "no query string provided"
+        );
+    }
+}
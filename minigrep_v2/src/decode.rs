@@ -0,0 +1,161 @@
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use std::io;
+use std::io::prelude::*;
+
+/// Splits a file into lines of UTF-8, transcoding them from whatever
+/// encoding the file is in, while keeping count of how many bytes of the
+/// original each line took up.
+pub struct Decoder {
+    // `None` passes bytes through untouched, valid UTF-8 or not
+    encoding: Option<&'static Encoding>,
+    raw: Vec<u8>,
+}
+
+impl Decoder {
+    /// Works out how to decode `reader`: a byte order mark at its start
+    /// wins, and otherwise `encoding` is used if there is one. Any byte
+    /// order mark is consumed, and its length returned.
+    pub fn sniff<R: BufRead>(
+        reader: &mut R,
+        encoding: Option<&'static Encoding>,
+    ) -> io::Result<(Decoder, usize)> {
+        let (encoding, bom) = match Encoding::for_bom(reader.fill_buf()?) {
+            Some((encoding, bom)) => (Some(encoding), bom),
+            None => (encoding, 0),
+        };
+        reader.consume(bom);
+        let decoder = Decoder {
+            // nothing to transcode, and no need to replace invalid bytes
+            encoding: encoding.filter(|&e| e != UTF_8),
+            raw: Vec::new(),
+        };
+        Ok((decoder, bom))
+    }
+
+    /// Whether the file is UTF-16, whose text is full of NUL bytes.
+    pub fn is_utf16(&self) -> bool {
+        self.encoding == Some(UTF_16LE) || self.encoding == Some(UTF_16BE)
+    }
+
+    /// Reads the next line, terminator included, into `line`, returning how
+    /// many bytes of the original it was (0 at the end of the file).
+    pub fn read_line<R: BufRead>(
+        &mut self,
+        reader: &mut R,
+        line: &mut Vec<u8>,
+    ) -> io::Result<usize> {
+        let encoding = match self.encoding {
+            Some(encoding) => encoding,
+            None => return reader.read_until(b'\n', line),
+        };
+        self.raw.clear();
+        if encoding == UTF_16LE {
+            read_utf16_line(reader, [b'\n', 0], &mut self.raw)?;
+        } else if encoding == UTF_16BE {
+            read_utf16_line(reader, [0, b'\n'], &mut self.raw)?;
+        } else {
+            // every other encoding we can be given spells `\n` as 0x0A
+            reader.read_until(b'\n', &mut self.raw)?;
+        }
+        let (text, _) = encoding.decode_without_bom_handling(&self.raw);
+        line.extend_from_slice(text.as_bytes());
+        Ok(self.raw.len())
+    }
+}
+
+// Like `read_until`, but a code unit at a time so that a byte of some other
+// character that happens to look like half a newline isn't taken for one.
+fn read_utf16_line<R: BufRead>(
+    reader: &mut R,
+    newline: [u8; 2],
+    raw: &mut Vec<u8>,
+) -> io::Result<()> {
+    loop {
+        let (done, used) = {
+            let buf = reader.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(());
+            }
+            let mut used = 0;
+            let mut done = false;
+            for &byte in buf {
+                raw.push(byte);
+                used += 1;
+                if raw.len().is_multiple_of(2) && raw[raw.len() - 2..] == newline {
+                    done = true;
+                    break;
+                }
+            }
+            (done, used)
+        };
+        reader.consume(used);
+        if done {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::WINDOWS_1252;
+
+    // every line read from `bytes`, with how long each was in the original
+    fn lines(bytes: &[u8], encoding: Option<&'static Encoding>) -> Vec<(String, usize)> {
+        let mut reader = io::BufReader::with_capacity(3, bytes);
+        let (mut decoder, _) = Decoder::sniff(&mut reader, encoding).unwrap();
+        let mut lines = Vec::new();
+        loop {
+            let mut line = Vec::new();
+            match decoder.read_line(&mut reader, &mut line).unwrap() {
+                0 => return lines,
+                n => lines.push((String::from_utf8_lossy(&line).into_owned(), n)),
+            }
+        }
+    }
+
+    fn utf16(text: &str, big_endian: bool) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for unit in text.encode_utf16() {
+            let unit = match big_endian {
+                true => unit.to_be_bytes(),
+                false => unit.to_le_bytes(),
+            };
+            bytes.extend_from_slice(&unit);
+        }
+        bytes
+    }
+
+    #[test]
+    fn decodes_utf16_with_either_byte_order_mark() {
+        let text = "\u{feff}na\u{ef0a}ve\r\n€5\n";
+        let expected = vec![
+            (String::from("na\u{ef0a}ve\r\n"), 14),
+            (String::from("€5\n"), 6),
+        ];
+        assert_eq!(lines(&utf16(text, false), None), expected);
+        assert_eq!(lines(&utf16(text, true), None), expected);
+    }
+
+    #[test]
+    fn uses_the_given_encoding_without_a_byte_order_mark() {
+        let bytes = b"caf\xe9\n\x80 5\n";
+        assert_eq!(
+            lines(bytes, Some(WINDOWS_1252)),
+            vec![(String::from("café\n"), 5), (String::from("€ 5\n"), 4)]
+        );
+        // but a byte order mark trumps it
+        assert_eq!(
+            lines(&utf16("\u{feff}é", false), Some(WINDOWS_1252)),
+            vec![(String::from("é"), 2)]
+        );
+    }
+
+    #[test]
+    fn passes_bytes_through_when_nothing_needs_decoding() {
+        assert_eq!(
+            lines(b"\xef\xbb\xbfok\n\xff\n", None),
+            vec![(String::from("ok\n"), 3), (String::from("\u{fffd}\n"), 2)]
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use unicode_normalization::char::canonical_combining_class;
+use unicode_normalization::UnicodeNormalization;
+
+/// A Unicode normalization form to bring text into before matching.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Normalization {
+    /// Canonical composition (`e` + `◌́` becomes `é`)
+    Nfc,
+    /// Canonical decomposition (`é` becomes `e` + `◌́`)
+    Nfd,
+}
+
+/// Rewrites text into a canonical form so that equivalent spellings compare
+/// equal, remembering where each piece of the result came from.
+///
+/// With `case_fold`, characters are fully case folded (`ß`, `SS` and `ẞ` all
+/// become `ss`; `ς` and `Σ` become `σ`) and typographic quotes become their
+/// ASCII counterparts, so a typed `'` finds `’`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Folder {
+    pub case_fold: bool,
+    pub normalization: Option<Normalization>,
+}
+
+/// Text rewritten by a `Folder`.
+pub struct Folded {
+    pub text: Vec<u8>,
+    // `(start in text, start in original, end in original)` for each unit of
+    // the original that was rewritten as a whole
+    units: Vec<(usize, usize, usize)>,
+}
+
+impl Folded {
+    /// Translates a byte range of the rewritten text into the smallest range
+    /// of whole units of the original that produced it.
+    pub fn original_span(&self, start: usize, end: usize) -> (usize, usize) {
+        let unit = |at: usize| match self.units.binary_search_by_key(&at, |u| u.0) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        if self.units.is_empty() {
+            return (0, 0);
+        }
+        if start == self.text.len() {
+            let end = self.units.last().unwrap().2;
+            return (end, end);
+        }
+        let first = self.units[unit(start)];
+        if end <= start {
+            return (first.1, first.1);
+        }
+        (first.1, self.units[unit(end - 1)].2)
+    }
+}
+
+impl Folder {
+    pub fn fold_str(&self, text: &str) -> String {
+        // only ever given valid UTF-8, so the output is valid too
+        String::from_utf8(self.fold(text.as_bytes()).text).unwrap()
+    }
+
+    pub fn fold(&self, line: &[u8]) -> Folded {
+        let mut folded = Folded {
+            text: Vec::with_capacity(line.len()),
+            units: Vec::new(),
+        };
+        let mut offset = 0;
+        for chunk in line.utf8_chunks() {
+            let valid = chunk.valid();
+            for (start, end) in units(valid) {
+                folded.units.push((folded.text.len(), offset + start, offset + end));
+                let rewritten = self.rewrite(&valid[start..end]);
+                folded.text.extend_from_slice(rewritten.as_bytes());
+            }
+            offset += valid.len();
+            // bytes that aren't UTF-8 can't be folded, so pass them through
+            for &byte in chunk.invalid() {
+                folded.units.push((folded.text.len(), offset, offset + 1));
+                folded.text.push(byte);
+                offset += 1;
+            }
+        }
+        folded
+    }
+
+    fn rewrite(&self, unit: &str) -> String {
+        let normalize = |s: String| match self.normalization {
+            Some(Normalization::Nfc) => s.nfc().collect(),
+            Some(Normalization::Nfd) => s.nfd().collect(),
+            None => s,
+        };
+        let text = normalize(unit.to_string());
+        if !self.case_fold {
+            return text;
+        }
+        // folding can produce decomposed characters (`İ` becomes `i` + `◌̇`),
+        // so normalize again afterwards
+        normalize(text.chars().flat_map(fold_char).collect())
+    }
+}
+
+// Full case folding, built from the standard library's case mappings:
+// lowercasing, uppercasing and lowercasing again sends every member of a
+// case-insensitive equivalence class to the same string (`ẞ` → `ß` → `SS` →
+// `ss`). Unlike `str::to_lowercase` this is applied a character at a time,
+// so it doesn't turn a word-final `Σ` into `ς`.
+fn fold_char(c: char) -> Vec<char> {
+    match c {
+        '\u{2018}' | '\u{2019}' | '\u{201B}' | '\u{2032}' => vec!['\''],
+        '\u{201C}' | '\u{201D}' | '\u{201F}' | '\u{2033}' => vec!['"'],
+        _ => c
+            .to_lowercase()
+            .flat_map(char::to_uppercase)
+            .flat_map(char::to_lowercase)
+            .collect(),
+    }
+}
+
+// Splits text into the smallest pieces normalization never reaches across:
+// a starter plus any combining marks after it. Hangul vowel and final
+// jamo are starters that still compose with what precedes them, so they
+// don't begin a new piece.
+fn units(text: &str) -> Vec<(usize, usize)> {
+    let mut units: Vec<(usize, usize)> = Vec::new();
+    for (i, c) in text.char_indices() {
+        let end = i + c.len_utf8();
+        let joins_previous = canonical_combining_class(c) != 0
+            || ('\u{1161}'..='\u{1175}').contains(&c)
+            || ('\u{11A8}'..='\u{11C2}').contains(&c);
+        match units.last_mut() {
+            Some(unit) if joins_previous => unit.1 = end,
+            _ => units.push((i, end)),
+        }
+    }
+    units
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FOLD: Folder = Folder {
+        case_fold: true,
+        normalization: None,
+    };
+
+    #[test]
+    fn folds_case_fully() {
+        assert_eq!(FOLD.fold_str("Straße STRASSE straẞe"), "strasse strasse strasse");
+        assert_eq!(FOLD.fold_str("ΣΟΦΟΣ σοφος"), "σοφοσ σοφοσ");
+        assert_eq!(FOLD.fold_str("ﬁle ſtop \u{212A}elvin"), "file stop kelvin");
+        assert_eq!(FOLD.fold_str("İ"), "i\u{307}");
+    }
+
+    #[test]
+    fn folds_typographic_quotes() {
+        assert_eq!(FOLD.fold_str("I’m “nobody”"), "i'm \"nobody\"");
+    }
+
+    #[test]
+    fn normalizes_to_the_requested_form() {
+        let nfc = Folder {
+            case_fold: false,
+            normalization: Some(Normalization::Nfc),
+        };
+        let nfd = Folder {
+            case_fold: false,
+            normalization: Some(Normalization::Nfd),
+        };
+        assert_eq!(nfc.fold_str("cafe\u{301} café"), "café café");
+        assert_eq!(nfd.fold_str("cafe\u{301} café"), "cafe\u{301} cafe\u{301}");
+        assert_eq!(nfc.fold_str("\u{1100}\u{1161}\u{11A8}"), "\u{AC01}");
+    }
+
+    #[test]
+    fn maps_spans_back_to_the_original_text() {
+        let folder = Folder {
+            case_fold: true,
+            normalization: Some(Normalization::Nfc),
+        };
+        let line = "Größe: STRAẞE, cafe\u{301}";
+        let folded = folder.fold(line.as_bytes());
+        let text = String::from_utf8(folded.text.clone()).unwrap();
+        assert_eq!(text, "grösse: strasse, café");
+
+        let find = |needle: &str| {
+            let start = text.find(needle).unwrap();
+            let (s, e) = folded.original_span(start, start + needle.len());
+            &line[s..e]
+        };
+        assert_eq!(find("strasse"), "STRAẞE");
+        assert_eq!(find("ss"), "ß");
+        assert_eq!(find("s"), "ß");
+        assert_eq!(find("café"), "cafe\u{301}");
+        assert_eq!(find("é"), "e\u{301}");
+    }
+
+    #[test]
+    fn passes_invalid_utf8_through() {
+        let folded = FOLD.fold(b"\xffABC\xfe");
+        assert_eq!(folded.text, b"\xffabc\xfe".to_vec());
+        assert_eq!(folded.original_span(1, 3), (1, 3));
+        assert_eq!(folded.original_span(5, 5), (5, 5));
+    }
+}
@@ -0,0 +1,235 @@
+/// Finds substrings within a given Levenshtein distance of any of a set of
+/// queries: at most that many characters inserted, deleted or substituted.
+/// Distances count characters, not bytes, and bytes that aren't valid
+/// UTF-8 count as a character each.
+///
+/// Searching uses Sellers' dynamic programming with Ukkonen's cutoff, which
+/// only works out the part of each column that can still end up within
+/// the distance. That takes about O(k·n) time for a text of n characters,
+/// rather than the O(m·n) it would for a query of m.
+#[derive(Debug)]
+pub struct Fuzzy {
+    queries: Vec<Vec<char>>,
+    max_distance: usize,
+    case_sensitive: bool,
+}
+
+impl Fuzzy {
+    /// Empty queries are dropped, since everything is near one.
+    pub fn new(queries: &[String], max_distance: usize, case_sensitive: bool) -> Fuzzy {
+        let queries = queries
+            .iter()
+            .filter(|query| !query.is_empty())
+            .map(|query| {
+                query
+                    .chars()
+                    .map(|c| if case_sensitive { c } else { lowercase(c) })
+                    .collect()
+            }).collect();
+        Fuzzy {
+            queries,
+            max_distance,
+            case_sensitive,
+        }
+    }
+
+    pub fn is_match(&self, text: &[u8]) -> bool {
+        let (chars, _) = self.chars(text);
+        self.queries
+            .iter()
+            .any(|query| self.find_at(query, &chars, 0).is_some())
+    }
+
+    /// `(start, end, distance)` for every non-overlapping match in `text`.
+    /// Matches are taken leftmost first, each one running on for as long as
+    /// that brings it no further from its query, and starting as early as
+    /// it can at the distance it ends up at.
+    pub fn find_all(&self, text: &[u8]) -> Vec<(usize, usize, usize)> {
+        let (chars, offsets) = self.chars(text);
+        // where each query next matches, no earlier than the last match
+        let mut next: Vec<_> = self
+            .queries
+            .iter()
+            .map(|query| self.find_at(query, &chars, 0))
+            .collect();
+        let mut found = Vec::new();
+        while let Some((start, end, distance)) =
+            next.iter().flatten().cloned().min_by_key(|&(start, _, distance)| (start, distance))
+        {
+            found.push((offsets[start], offsets[end], distance));
+            for (query, next) in self.queries.iter().zip(&mut next) {
+                if next.is_some_and(|(start, _, _)| start < end) {
+                    *next = self.find_at(query, &chars, end);
+                }
+            }
+        }
+        found
+    }
+
+    // The first match of `query` in `chars` starting from `from`, as
+    // `(start, end, distance)` in characters.
+    fn find_at(
+        &self,
+        query: &[char],
+        chars: &[char],
+        from: usize,
+    ) -> Option<(usize, usize, usize)> {
+        let (m, k) = (query.len(), self.max_distance);
+        // `column[i]` is the distance from `query[..i]` to the closest
+        // substring ending at the current position; a match can start
+        // anywhere, so row 0 stays 0
+        let mut column: Vec<usize> = (0..=m).collect();
+        // the last row within `k`: every row below it is further off, and
+        // isn't worth keeping exact since adding to it can't bring it back
+        let mut last = k.min(m);
+        // where the best match in the current run of matches ends
+        let mut best: Option<(usize, usize)> = None;
+        for (j, &c) in chars.iter().enumerate().skip(from) {
+            let bottom = (last + 1).min(m);
+            let mut diagonal = 0;
+            for i in 1..=bottom {
+                let cost = if query[i - 1] == c { 0 } else { 1 };
+                let distance = (diagonal + cost).min(column[i] + 1).min(column[i - 1] + 1);
+                diagonal = column[i];
+                column[i] = distance;
+            }
+            last = bottom;
+            while column[last] > k {
+                last -= 1;
+            }
+            match best {
+                // a neighbour that's as close takes in more of the text, and
+                // one that's closer is simply better
+                Some((_, distance)) if last == m && column[m] <= distance => {
+                    best = Some((j + 1, column[m]));
+                }
+                None if last == m => best = Some((j + 1, column[m])),
+                Some(_) => break,
+                None => {}
+            }
+        }
+        let (end, distance) = best?;
+        Some((self.start(query, chars, from, end, distance), end, distance))
+    }
+
+    // Where the match ending at `end` starts: the earliest start that keeps
+    // it `distance` away from `query`, found by running the distance
+    // calculation backwards from `end`.
+    fn start(
+        &self,
+        query: &[char],
+        chars: &[char],
+        from: usize,
+        end: usize,
+        distance: usize,
+    ) -> usize {
+        let m = query.len();
+        // `row[i]` is the distance from the last `i` characters of `query`
+        // to the text between `start` and `end`
+        let mut row: Vec<usize> = (0..=m).collect();
+        let mut earliest = None;
+        // any longer and the extra characters alone would be too many
+        let from = from.max(end.saturating_sub(m + distance));
+        for start in (from..end).rev() {
+            let mut diagonal = row[0];
+            row[0] = end - start;
+            for i in 1..=m {
+                let cost = if query[m - i] == chars[start] { 0 } else { 1 };
+                let d = (diagonal + cost).min(row[i] + 1).min(row[i - 1] + 1);
+                diagonal = row[i];
+                row[i] = d;
+            }
+            if row[m] == distance {
+                earliest = Some(start);
+            }
+        }
+        // the forward search only reports distances some start achieves
+        earliest.expect("a match has a start")
+    }
+
+    // The characters of `text`, and the byte offset each starts at (with
+    // one more for the end of the text).
+    fn chars(&self, text: &[u8]) -> (Vec<char>, Vec<usize>) {
+        let mut chars = Vec::with_capacity(text.len());
+        let mut offsets = Vec::with_capacity(text.len() + 1);
+        let mut offset = 0;
+        for chunk in text.utf8_chunks() {
+            for (i, c) in chunk.valid().char_indices() {
+                chars.push(if self.case_sensitive { c } else { lowercase(c) });
+                offsets.push(offset + i);
+            }
+            offset += chunk.valid().len();
+            for _ in chunk.invalid() {
+                chars.push(char::REPLACEMENT_CHARACTER);
+                offsets.push(offset);
+                offset += 1;
+            }
+        }
+        offsets.push(text.len());
+        (chars, offsets)
+    }
+}
+
+// good enough for comparing characters one by one; the few that lowercase
+// to several characters keep their first
+fn lowercase(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find<'a>(queries: &[&str], k: usize, text: &'a str) -> Vec<(&'a str, usize)> {
+        let queries: Vec<String> = queries.iter().map(|q| q.to_string()).collect();
+        Fuzzy::new(&queries, k, true)
+            .find_all(text.as_bytes())
+            .into_iter()
+            .map(|(start, end, distance)| (&text[start..end], distance))
+            .collect()
+    }
+
+    #[test]
+    fn finds_substrings_within_the_distance() {
+        let text = "I'm nobdy! Who are you? Are you nobody, too? Or noboody?";
+        assert_eq!(
+            find(&["nobody"], 1, text),
+            vec![("nobdy", 1), ("nobody", 0), ("noboody", 1)]
+        );
+        assert_eq!(find(&["nobody"], 0, text), vec![("nobody", 0)]);
+        assert_eq!(find(&["nobody"], 1, "nbdy, mobody, ybodon"), vec![("mobody", 1)]);
+    }
+
+    #[test]
+    fn prefers_the_closest_and_longest_match() {
+        assert_eq!(find(&["frog"], 1, "a froggy day"), vec![("frog", 0)]);
+        // "frgo" is a match too, but "frg" is closer
+        assert_eq!(find(&["frog"], 2, "a frgo"), vec![("frg", 1)]);
+        // "b" is as close as "xb", but leaves out a character
+        assert_eq!(find(&["ab"], 1, "xb"), vec![("xb", 1)]);
+    }
+
+    #[test]
+    fn counts_characters_not_bytes() {
+        assert_eq!(find(&["café"], 1, "a cafe, a cafè"), vec![("cafe", 1), ("cafè", 1)]);
+        let fuzzy = Fuzzy::new(&[String::from("ab")], 1, true);
+        assert_eq!(fuzzy.find_all(b"x\xffb"), vec![(1, 3, 1)]);
+    }
+
+    #[test]
+    fn takes_whichever_query_matches_first() {
+        assert_eq!(
+            find(&["toad", "bog", ""], 1, "a bag and a tod"),
+            vec![("bag", 1), ("tod", 1)]
+        );
+        assert!(find(&[""], 1, "anything").is_empty());
+    }
+
+    #[test]
+    fn ignores_case_when_asked() {
+        let fuzzy = Fuzzy::new(&[String::from("Nobody")], 1, false);
+        assert!(fuzzy.is_match(b"NOBDY"));
+        assert!(!fuzzy.is_match(b"NBDY"));
+        assert_eq!(fuzzy.find_all(b"x NOBODY"), vec![(2, 8, 0)]);
+    }
+}
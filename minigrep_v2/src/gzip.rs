@@ -0,0 +1,74 @@
+#[cfg(feature = "gzip")]
+use flate2::bufread::MultiGzDecoder;
+use std::io;
+use std::io::prelude::*;
+#[cfg(feature = "gzip")]
+use std::io::BufReader;
+
+// How every gzip member starts.
+const MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// Passes `reader` on as it is, unless it starts like gzip data, in which
+/// case it's decompressed as it's read. Any number of gzip members one
+/// after another are read as one stream, as `gzip -d` does.
+pub fn decompressed<'a, R: BufRead + 'a>(mut reader: R) -> io::Result<Box<dyn BufRead + 'a>> {
+    if reader.fill_buf()?.starts_with(MAGIC) {
+        decompress(reader)
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+#[cfg(feature = "gzip")]
+fn decompress<'a, R: BufRead + 'a>(reader: R) -> io::Result<Box<dyn BufRead + 'a>> {
+    Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+}
+
+#[cfg(not(feature = "gzip"))]
+fn decompress<'a, R: BufRead + 'a>(_reader: R) -> io::Result<Box<dyn BufRead + 'a>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "can't decompress gzip data without the `gzip` feature",
+    ))
+}
+
+#[cfg(all(test, feature = "gzip"))]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    fn gzip(text: &str) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(text.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn read(bytes: &[u8]) -> String {
+        let mut text = String::new();
+        decompressed(bytes).unwrap().read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn decompresses_only_what_looks_like_gzip() {
+        assert_eq!(read(&gzip("a frog\n")), "a frog\n");
+        assert_eq!(read(b"a frog\n"), "a frog\n");
+        assert_eq!(read(b""), "");
+    }
+
+    #[test]
+    fn reads_members_one_after_another() {
+        let mut bytes = gzip("first\n");
+        bytes.extend(gzip("second\n"));
+        assert_eq!(read(&bytes), "first\nsecond\n");
+    }
+
+    #[test]
+    fn reports_corrupt_data() {
+        let mut bytes = gzip("a frog\n");
+        bytes.truncate(bytes.len() / 2);
+        let mut text = String::new();
+        assert!(decompressed(&bytes[..]).unwrap().read_to_string(&mut text).is_err());
+    }
+}
@@ -0,0 +1,289 @@
+use matcher::describe;
+use regex::Regex;
+
+/// A list of gitignore-style patterns, where the last one that matches a
+/// path decides what happens to it.
+///
+/// Patterns follow `.gitignore` syntax: `*`, `?` and `[...]` never match a
+/// `/`; `**` matches across directories; a trailing `/` only matches
+/// directories; and a pattern containing a `/` other than at its end is
+/// anchored to the directory it applies to, while one without matches at
+/// any depth.
+#[derive(Debug, Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+    // whether any rule selects paths, in which case unmatched files don't
+    // count as selected
+    has_includes: bool,
+}
+
+#[derive(Debug)]
+struct Rule {
+    regex: Regex,
+    dir_only: bool,
+    // `true` if matching paths are wanted (a `!pattern` in an ignore file,
+    // a plain `--glob`), `false` if they're to be skipped
+    include: bool,
+}
+
+/// What a set of rules makes of a path.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Verdict {
+    Include,
+    Exclude,
+    /// No rule says anything about the path
+    Unmatched,
+}
+
+impl Rules {
+    /// Parses the contents of a `.gitignore` or `.ignore` file. Lines that
+    /// can't be made sense of are skipped, as git does.
+    pub fn ignore_file(text: &str) -> Rules {
+        let rules = text
+            .lines()
+            .filter_map(|line| parse(line).ok().and_then(|rule| rule))
+            .map(|(rule, negated)| Rule {
+                include: negated,
+                ..rule
+            }).collect();
+        Rules {
+            rules,
+            has_includes: false,
+        }
+    }
+
+    /// Parses `--glob` filters: `glob` selects matching paths, `!glob`
+    /// excludes them.
+    pub fn globs(globs: &[String]) -> Result<Rules, String> {
+        let mut rules = Rules::default();
+        for glob in globs {
+            let (rule, negated) = match parse(glob) {
+                Ok(Some(rule)) => rule,
+                Ok(None) => continue,
+                Err(reason) => return Err(format!("invalid glob `{}`: {}", glob, reason)),
+            };
+            rules.has_includes |= !negated;
+            rules.rules.push(Rule {
+                include: !negated,
+                ..rule
+            });
+        }
+        Ok(rules)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Judges `path`, given relative to wherever the rules apply and with
+    /// `/` between its components.
+    pub fn verdict(&self, path: &str, is_dir: bool) -> Verdict {
+        let rule = self
+            .rules
+            .iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.dir_only) && rule.regex.is_match(path));
+        match rule {
+            Some(rule) if rule.include => Verdict::Include,
+            Some(_) => Verdict::Exclude,
+            // once something has been asked for, files that weren't aren't
+            // wanted; directories still need to be looked inside, though
+            None if self.has_includes && !is_dir => Verdict::Exclude,
+            None => Verdict::Unmatched,
+        }
+    }
+}
+
+// Turns one line into a rule and whether it was negated with `!`, or
+// nothing for blank lines and comments.
+fn parse(line: &str) -> Result<Option<(Rule, bool)>, String> {
+    let line = line.trim_end_matches('\r');
+    // trailing spaces don't count unless escaped
+    let line = match line.ends_with("\\ ") {
+        true => line,
+        false => line.trim_end_matches(' '),
+    };
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let (negated, line) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let (dir_only, line) = match line.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let anchored = line.contains('/');
+    let line = line.strip_prefix('/').unwrap_or(line);
+    let pattern = match anchored {
+        true => format!("^{}$", translate(line)),
+        false => format!("^(?:.*/)?{}$", translate(line)),
+    };
+    let regex = Regex::new(&pattern).map_err(describe)?;
+    Ok(Some((
+        Rule {
+            regex,
+            dir_only,
+            include: false,
+        },
+        negated,
+    )))
+}
+
+// Translates glob syntax into the equivalent regex.
+fn translate(glob: &str) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut regex = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                let starts_component = i == 0 || chars[i - 1] == '/';
+                match chars.get(i + 2) {
+                    // `dir/**` is everything inside dir
+                    None if starts_component => regex.push_str(".*"),
+                    // `**/` is any number of directories, including none
+                    Some('/') if starts_component => {
+                        regex.push_str("(?:.*/)?");
+                        i += 1;
+                    }
+                    // anywhere else it's just two stars
+                    _ => regex.push_str("[^/]*"),
+                }
+                i += 2;
+                continue;
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => match class(&chars[i..]) {
+                Some((class, len)) => {
+                    regex.push_str(&class);
+                    i += len;
+                    continue;
+                }
+                None => regex.push_str(r"\["),
+            },
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                regex.push_str(&regex::escape(&chars[i].to_string()));
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    regex
+}
+
+// Translates the bracket expression at the start of `chars`, returning it
+// along with how many characters it took up, or nothing if it's unclosed.
+fn class(chars: &[char]) -> Option<(String, usize)> {
+    let mut i = 1;
+    let mut class = String::from("[");
+    if let Some('!') | Some('^') = chars.get(i) {
+        class.push('^');
+        i += 1;
+    }
+    // a `]` straight after the opening bracket is part of the class
+    let first = i;
+    while i < chars.len() {
+        match chars[i] {
+            ']' if i > first => {
+                class.push(']');
+                return Some((class, i + 1));
+            }
+            '-' => class.push('-'),
+            c => class.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn judge(rules: &Rules, paths: &[(&str, bool)]) -> Vec<Verdict> {
+        paths
+            .iter()
+            .map(|&(path, is_dir)| rules.verdict(path, is_dir))
+            .collect()
+    }
+
+    #[test]
+    fn matches_unanchored_patterns_at_any_depth() {
+        let rules = Rules::ignore_file("# build output\n*.o\n\ntarget\n");
+        assert_eq!(
+            judge(
+                &rules,
+                &[("a.o", false), ("src/deep/b.o", false), ("target", true), ("src/target", false)]
+            ),
+            vec![Verdict::Exclude; 4]
+        );
+        assert_eq!(rules.verdict("a.rs", false), Verdict::Unmatched);
+        assert_eq!(rules.verdict("a.o.rs", false), Verdict::Unmatched);
+    }
+
+    #[test]
+    fn anchors_patterns_containing_a_slash() {
+        let rules = Rules::ignore_file("/build\ndoc/*.html\n");
+        assert_eq!(rules.verdict("build", true), Verdict::Exclude);
+        assert_eq!(rules.verdict("src/build", true), Verdict::Unmatched);
+        assert_eq!(rules.verdict("doc/index.html", false), Verdict::Exclude);
+        assert_eq!(rules.verdict("doc/api/index.html", false), Verdict::Unmatched);
+        assert_eq!(rules.verdict("src/doc/index.html", false), Verdict::Unmatched);
+    }
+
+    #[test]
+    fn matches_directories_only_when_asked() {
+        let rules = Rules::ignore_file("logs/\n");
+        assert_eq!(rules.verdict("logs", true), Verdict::Exclude);
+        assert_eq!(rules.verdict("app/logs", true), Verdict::Exclude);
+        assert_eq!(rules.verdict("logs", false), Verdict::Unmatched);
+    }
+
+    #[test]
+    fn lets_later_negations_win() {
+        let rules = Rules::ignore_file("*.log\n!keep.log\nbuild/keep.log\n");
+        assert_eq!(rules.verdict("debug.log", false), Verdict::Exclude);
+        assert_eq!(rules.verdict("keep.log", false), Verdict::Include);
+        assert_eq!(rules.verdict("build/keep.log", false), Verdict::Exclude);
+    }
+
+    #[test]
+    fn supports_double_stars_classes_and_escapes() {
+        let rules = Rules::ignore_file("**/cache\nout/**\na/**/z\nv[0-9].txt\n\\#notes\nx\\ \n");
+        assert_eq!(rules.verdict("cache", true), Verdict::Exclude);
+        assert_eq!(rules.verdict("deep/down/cache", true), Verdict::Exclude);
+        assert_eq!(rules.verdict("out/any/thing", false), Verdict::Exclude);
+        assert_eq!(rules.verdict("out", true), Verdict::Unmatched);
+        assert_eq!(rules.verdict("a/z", false), Verdict::Exclude);
+        assert_eq!(rules.verdict("a/b/c/z", false), Verdict::Exclude);
+        assert_eq!(rules.verdict("v7.txt", false), Verdict::Exclude);
+        assert_eq!(rules.verdict("vx.txt", false), Verdict::Unmatched);
+        assert_eq!(rules.verdict("#notes", false), Verdict::Exclude);
+        assert_eq!(rules.verdict("x ", false), Verdict::Exclude);
+    }
+
+    #[test]
+    fn includes_and_excludes_files_by_glob() {
+        let globs = |globs: &[&str]| {
+            Rules::globs(&globs.iter().map(|g| g.to_string()).collect::<Vec<_>>()).unwrap()
+        };
+        let rules = globs(&["*.rs", "!build.rs"]);
+        assert_eq!(rules.verdict("src/main.rs", false), Verdict::Include);
+        assert_eq!(rules.verdict("build.rs", false), Verdict::Exclude);
+        assert_eq!(rules.verdict("Cargo.lock", false), Verdict::Exclude);
+        assert_eq!(rules.verdict("src", true), Verdict::Unmatched);
+
+        let rules = globs(&["!*.lock"]);
+        assert_eq!(rules.verdict("Cargo.lock", false), Verdict::Exclude);
+        assert_eq!(rules.verdict("Cargo.toml", false), Verdict::Unmatched);
+
+        assert_eq!(
+            Rules::globs(&[String::from("[z-a]")]).unwrap_err(),
+            "invalid glob `[z-a]`: invalid character class range, the start must be <= the end"
+        );
+    }
+}
@@ -0,0 +1,505 @@
+use encoding_rs::Encoding;
+use matcher::Matcher;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+use walk::{relative, Walk};
+use Config;
+
+/// What the index is called within the directory it covers. Being hidden,
+/// it's left out of searches unless they ask for hidden files.
+pub const FILE_NAME: &str = ".minigrep_v2-index";
+
+// The first bytes of an index file, which change whenever the format does.
+const MAGIC: &[u8] = b"minigrep_v2 index 1\n";
+
+/// The trigrams (runs of three bytes) in every file under a directory,
+/// along with each file's size and modification time when it was indexed,
+/// so that a search can skip files that can't possibly match.
+///
+/// Files are keyed by their path relative to the directory, and only count
+/// as indexed while their size and modification time haven't changed. As
+/// git does, a file modified no earlier than the index was built isn't
+/// trusted either, since it could have changed again within the same tick
+/// of the clock.
+#[derive(Debug, Default)]
+pub struct Index {
+    // when the index was started, as seconds and nanoseconds since the
+    // epoch, by the filesystem's clock rather than the system's so that it
+    // compares fairly with modification times however coarse they are
+    built: (u64, u32),
+    files: HashMap<String, Entry>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Entry {
+    size: u64,
+    modified: (u64, u32),
+    // whether the file's first block has a NUL in it, as the searcher checks
+    binary: bool,
+    // whether the file is searched as it is, without a byte order mark to
+    // be decoded (or dropped) first
+    plain: bool,
+    // sorted, so they can be looked up with a binary search
+    trigrams: Vec<u32>,
+}
+
+/// What `Index::update` found had changed since the last time.
+#[derive(Debug, Default, PartialEq)]
+pub struct Update {
+    pub added: usize,
+    pub changed: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+impl Index {
+    /// Loads the index in `dir`.
+    pub fn load(dir: &Path) -> io::Result<Index> {
+        let path = dir.join(FILE_NAME);
+        let file = File::open(&path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => io::Error::new(
+                e.kind(),
+                format!(
+                    "{}: no index here (build one with `minigrep_v2 index {}`)",
+                    dir.display(),
+                    dir.display()
+                ),
+            ),
+            _ => io::Error::new(e.kind(), format!("{}: {}", path.display(), e)),
+        })?;
+        Index::read(&mut BufReader::new(file))
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    /// Brings the index in `dir` up to date with the files in it, building
+    /// one from scratch if there isn't one yet (or it can't be read). Only
+    /// files that are new or have changed since are read.
+    ///
+    /// Files are found the way a search finds them by default, respecting
+    /// ignore files and skipping hidden ones. Anything that can't be read
+    /// is left out, to be searched (and complained about) as usual.
+    pub fn update(dir: &Path) -> io::Result<Update> {
+        let mut old = Index::load(dir).unwrap_or_default();
+        // made before anything is scanned, for a time that comes before any
+        // change the scan could have missed
+        let temp = dir.join(format!("{}-{}", FILE_NAME, process::id()));
+        let file = OpenOptions::new().write(true).create_new(true).open(&temp)?;
+        let built = file.metadata().and_then(|m| m.modified()).ok().and_then(timestamp);
+        let mut index = Index {
+            // with no telling, nothing is trusted
+            built: built.unwrap_or_default(),
+            files: HashMap::new(),
+        };
+        let mut update = Update::default();
+        for path in Walk::new(dir, false, None).filter_map(Result::ok) {
+            let key = relative(dir, &path);
+            let metadata = match fs::metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            let entry = match old.files.remove(&key) {
+                Some(entry) if old.is_fresh(&entry, &metadata) => {
+                    update.unchanged += 1;
+                    entry
+                }
+                previous => match scan(&path, &metadata) {
+                    Ok(Some(entry)) => {
+                        match previous {
+                            Some(_) => update.changed += 1,
+                            None => update.added += 1,
+                        }
+                        entry
+                    }
+                    _ => {
+                        update.removed += previous.is_some() as usize;
+                        continue;
+                    }
+                },
+            };
+            index.files.insert(key, entry);
+        }
+        // whatever's left wasn't found this time
+        update.removed += old.files.len();
+        index.save(file, &temp, dir)?;
+        Ok(update)
+    }
+
+    /// Whether the file at `path`, found under `root`, is indexed and
+    /// shown by the index to have nothing `query` could match. If so, this
+    /// is `Some` of whether the file is binary.
+    pub fn rules_out(&self, root: &Path, path: &Path, query: &Query) -> Option<bool> {
+        let entry = self.files.get(&relative(root, path))?;
+        let metadata = fs::metadata(path).ok()?;
+        if !self.is_fresh(entry, &metadata) || !entry.plain || query.may_match(&entry.trigrams) {
+            return None;
+        }
+        Some(entry.binary)
+    }
+
+    fn is_fresh(&self, entry: &Entry, metadata: &fs::Metadata) -> bool {
+        let modified = metadata.modified().ok().and_then(timestamp);
+        entry.size == metadata.len()
+            && modified == Some(entry.modified)
+            && entry.modified < self.built
+    }
+
+    // Written to a temporary file and renamed into place, so that searches
+    // never see half an index.
+    fn save(&self, file: File, temp: &Path, dir: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(file);
+        let written = self
+            .write(&mut out)
+            .and_then(|()| out.into_inner().map_err(|e| e.into_error()))
+            .and_then(|file| file.sync_all())
+            .and_then(|()| fs::rename(temp, dir.join(FILE_NAME)));
+        if written.is_err() {
+            let _ = fs::remove_file(temp);
+        }
+        written
+    }
+
+    // The format is the magic line, then the build time and the number of
+    // files, then each file's path, size, modification time, flags and
+    // trigrams, with every number little-endian.
+    fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_all(&self.built.0.to_le_bytes())?;
+        out.write_all(&self.built.1.to_le_bytes())?;
+        out.write_all(&(self.files.len() as u64).to_le_bytes())?;
+        let mut keys: Vec<_> = self.files.keys().collect();
+        keys.sort();
+        for key in keys {
+            let entry = &self.files[key];
+            out.write_all(&(key.len() as u32).to_le_bytes())?;
+            out.write_all(key.as_bytes())?;
+            out.write_all(&entry.size.to_le_bytes())?;
+            out.write_all(&entry.modified.0.to_le_bytes())?;
+            out.write_all(&entry.modified.1.to_le_bytes())?;
+            out.write_all(&[entry.binary as u8 | (entry.plain as u8) << 1])?;
+            out.write_all(&(entry.trigrams.len() as u32).to_le_bytes())?;
+            for trigram in &entry.trigrams {
+                out.write_all(&trigram.to_le_bytes()[..3])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read<R: Read>(input: &mut R) -> io::Result<Index> {
+        let mut magic = [0; MAGIC.len()];
+        input.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not an index, or one from another version",
+            ));
+        }
+        let built = (read_u64(input)?, read_u32(input)?);
+        let count = read_u64(input)?;
+        let mut files = HashMap::new();
+        for _ in 0..count {
+            let len = read_u32(input)?;
+            let key = String::from_utf8(read_bytes(input, u64::from(len))?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let size = read_u64(input)?;
+            let modified = (read_u64(input)?, read_u32(input)?);
+            let mut flags = [0];
+            input.read_exact(&mut flags)?;
+            let len = read_u32(input)?;
+            let trigrams = read_bytes(input, u64::from(len) * 3)?
+                .chunks(3)
+                .map(|t| u32::from_le_bytes([t[0], t[1], t[2], 0]))
+                .collect();
+            let entry = Entry {
+                size,
+                modified,
+                binary: flags[0] & 1 != 0,
+                plain: flags[0] & 2 != 0,
+                trigrams,
+            };
+            files.insert(key, entry);
+        }
+        Ok(Index { built, files })
+    }
+}
+
+/// The trigrams a file has to contain for a search to have any chance of
+/// matching it: all of those in at least one of the literals that every
+/// match starts with.
+#[derive(Debug, PartialEq)]
+pub struct Query {
+    alternatives: Vec<Vec<u32>>,
+}
+
+impl Query {
+    /// Works out what files searched with `matcher` need to contain, or
+    /// returns `None` if the index can't tell which ones those are.
+    pub fn new(cfg: &Config, matcher: &Matcher) -> Option<Query> {
+        // inverted searches select lines from files without any matches, and
+        // decoding or unpacking a file means searching bytes other than the
+        // ones that were indexed
+        if cfg.invert_match || cfg.encoding.is_some() || cfg.search_zip || cfg.archives {
+            return None;
+        }
+        let alternatives: Vec<Vec<u32>> = matcher
+            .prefixes(cfg)?
+            .iter()
+            .map(|prefix| {
+                let mut trigrams: Vec<u32> = trigrams(prefix).collect();
+                trigrams.sort_unstable();
+                trigrams.dedup();
+                trigrams
+            }).collect();
+        // a prefix too short to have a trigram could be anywhere
+        if alternatives.iter().any(Vec::is_empty) {
+            return None;
+        }
+        Some(Query { alternatives })
+    }
+
+    fn may_match(&self, trigrams: &[u32]) -> bool {
+        self.alternatives
+            .iter()
+            .any(|wanted| wanted.iter().all(|t| trigrams.binary_search(t).is_ok()))
+    }
+}
+
+// Reads the file at `path` for its entry in the index.
+fn scan(path: &Path, metadata: &fs::Metadata) -> io::Result<Option<Entry>> {
+    let modified = match metadata.modified().ok().and_then(timestamp) {
+        Some(modified) => modified,
+        // without a usable modification time, there'd be no telling when
+        // the entry was out of date
+        None => return Ok(None),
+    };
+    // the same size of buffer as the searcher's, so that the first block
+    // is the same one it checks for a NUL
+    let mut reader = BufReader::new(File::open(path)?);
+    let first = reader.fill_buf()?;
+    let binary = first.contains(&0);
+    let plain = Encoding::for_bom(first).is_none();
+    let mut found = HashSet::new();
+    // the last two bytes read, to catch trigrams that straddle two blocks
+    let mut tail: Vec<u8> = Vec::new();
+    loop {
+        let block = reader.fill_buf()?;
+        if block.is_empty() {
+            break;
+        }
+        let straddling = [&tail[..], &block[..block.len().min(2)]].concat();
+        found.extend(trigrams(&straddling));
+        found.extend(trigrams(block));
+        tail.extend_from_slice(&block[block.len().saturating_sub(2)..]);
+        let excess = tail.len().saturating_sub(2);
+        tail.drain(..excess);
+        let len = block.len();
+        reader.consume(len);
+    }
+    let mut trigrams: Vec<u32> = found.into_iter().collect();
+    trigrams.sort_unstable();
+    Ok(Some(Entry {
+        size: metadata.len(),
+        modified,
+        binary,
+        plain,
+        trigrams,
+    }))
+}
+
+fn trigrams(bytes: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    bytes
+        .windows(3)
+        .map(|w| u32::from(w[0]) | u32::from(w[1]) << 8 | u32::from(w[2]) << 16)
+}
+
+fn timestamp(time: SystemTime) -> Option<(u64, u32)> {
+    let since = time.duration_since(UNIX_EPOCH).ok()?;
+    Some((since.as_secs(), since.subsec_nanos()))
+}
+
+fn read_u64<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_u32<R: Read>(input: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+// Reads `len` bytes, without trusting a length from a file that may be
+// corrupt enough to set aside room for them all up front.
+fn read_bytes<R: Read>(input: &mut R, len: u64) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    input.by_ref().take(len).read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common;
+    use matcher::MatcherKind;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = common::fixture_dir(
+            &format!("index-{}", name),
+            &[("poem.txt", "How public, like a frog\n"), ("a/bog.txt", "To an admiring bog!\n")],
+        );
+        backdate(&dir);
+        dir
+    }
+
+    // Sets every file under `dir` to an hour old, since files modified in the
+    // same tick of the filesystem's clock as an index was built aren't
+    // trusted by it, and a coarse clock's ticks are long.
+    fn backdate(dir: &Path) {
+        let hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        for path in Walk::new(dir, false, None).map(Result::unwrap) {
+            let file = OpenOptions::new().write(true).open(path).unwrap();
+            file.set_modified(hour_ago).unwrap();
+        }
+    }
+
+    fn query(pattern: &str, kind: MatcherKind) -> Option<Query> {
+        let cfg = Config {
+            patterns: vec![pattern.to_string()],
+            matcher: kind,
+            ..Config::default()
+        };
+        Query::new(&cfg, &Matcher::from_config(&cfg).unwrap())
+    }
+
+    // the files under `dir` that the index doesn't rule out for `pattern`
+    fn candidates(dir: &Path, pattern: &str) -> Vec<String> {
+        let index = Index::load(dir).unwrap();
+        let query = query(pattern, MatcherKind::Regex).unwrap();
+        let mut candidates: Vec<_> = Walk::new(dir, false, None)
+            .map(Result::unwrap)
+            .filter(|path| index.rules_out(dir, path, &query).is_none())
+            .map(|path| relative(dir, &path))
+            .collect();
+        candidates.sort();
+        candidates
+    }
+
+    #[test]
+    fn rules_out_files_without_the_querys_trigrams() {
+        let dir = scratch_dir("rules-out");
+        Index::update(&dir).unwrap();
+        assert_eq!(candidates(&dir, "frog|toad"), vec!["poem.txt"]);
+        assert_eq!(candidates(&dir, "(?i)ADMIRING"), vec!["a/bog.txt"]);
+        assert!(candidates(&dir, "newt").is_empty());
+        assert_eq!(candidates(&dir, r"(bog|frog)\b"), vec!["a/bog.txt", "poem.txt"]);
+    }
+
+    #[test]
+    fn updates_only_what_changed() {
+        let dir = scratch_dir("update");
+        assert_eq!(
+            Index::update(&dir).unwrap(),
+            Update {
+                added: 2,
+                ..Update::default()
+            }
+        );
+        // changed files aren't trusted until they're indexed again
+        fs::write(dir.join("a/bog.txt"), "To an admiring newt!\n").unwrap();
+        fs::write(dir.join("new.txt"), "a newt\n").unwrap();
+        assert_eq!(candidates(&dir, "newt"), vec!["a/bog.txt", "new.txt"]);
+        fs::remove_file(dir.join("poem.txt")).unwrap();
+        backdate(&dir);
+        assert_eq!(
+            Index::update(&dir).unwrap(),
+            Update {
+                added: 1,
+                changed: 1,
+                removed: 1,
+                unchanged: 0,
+            }
+        );
+        assert_eq!(candidates(&dir, "newt"), vec!["a/bog.txt", "new.txt"]);
+        assert!(candidates(&dir, "bog!").is_empty());
+        assert_eq!(Index::update(&dir).unwrap().unchanged, 2);
+
+        // modified no earlier than indexing started, so it could have been
+        // changed again since without its modification time showing it
+        let file = OpenOptions::new().write(true).open(dir.join("new.txt")).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(3600)).unwrap();
+        Index::update(&dir).unwrap();
+        assert_eq!(candidates(&dir, "frog"), vec!["new.txt"]);
+    }
+
+    #[test]
+    fn refuses_corrupt_lengths_without_allocating_them() {
+        let dir = scratch_dir("corrupt");
+        Index::update(&dir).unwrap();
+        let index = fs::read(dir.join(FILE_NAME)).unwrap();
+        // the length of the first path, and then of its trigrams
+        let key = MAGIC.len() + 20;
+        let trigrams = key + 4 + "a/bog.txt".len() + 21;
+        for &at in &[key, trigrams] {
+            let mut corrupt = index.clone();
+            corrupt[at..at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+            let err = Index::read(&mut &corrupt[..]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
+
+    #[test]
+    fn records_what_the_searcher_would_see() {
+        let dir = scratch_dir("scan");
+        // a trigram split across the searcher's first two blocks
+        let mut long = vec![b'.'; 8 * 1024 - 1];
+        long.extend(b"frog\0");
+        fs::write(dir.join("long.bin"), &long).unwrap();
+        fs::write(dir.join("utf16.txt"), b"\xff\xfef\0r\0o\0g\0").unwrap();
+        backdate(&dir);
+        Index::update(&dir).unwrap();
+
+        let index = Index::load(&dir).unwrap();
+        let entry = &index.files["long.bin"];
+        assert!(!entry.binary && entry.plain);
+        assert_eq!(candidates(&dir, r"\.fr"), vec!["long.bin", "utf16.txt"]);
+        assert!(!index.files["utf16.txt"].plain);
+    }
+
+    #[test]
+    fn only_narrows_searches_it_can_vouch_for() {
+        assert!(query("frog", MatcherKind::Literal).is_some());
+        assert!(query("fr", MatcherKind::Literal).is_none());
+        assert!(query("fr.g", MatcherKind::Regex).is_none());
+        let frog = |cfg: Config| {
+            let cfg = Config {
+                patterns: vec![String::from("frog")],
+                ..cfg
+            };
+            Query::new(&cfg, &Matcher::from_config(&cfg).unwrap())
+        };
+        assert!(frog(Config {
+            invert_match: true,
+            ..Config::default()
+        }).is_none());
+        assert!(frog(Config {
+            search_zip: true,
+            ..Config::default()
+        }).is_none());
+        assert!(frog(Config {
+            fuzzy: Some(1),
+            ..Config::default()
+        }).is_none());
+    }
+}
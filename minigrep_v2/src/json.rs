@@ -0,0 +1,235 @@
+use search::{Event, Match, Sink};
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::str;
+
+/// Writes search results to `out` as JSON Lines: one object per file
+/// begun, line found and file finished, each tagged with its `type`.
+///
+/// Anything read from a file is written as `{"text": "..."}` when it's
+/// valid UTF-8 and as `{"bytes": "<base64>"}` when it isn't, so that no
+/// input is ever mangled on its way out. That goes for paths too, which are
+/// written as the bytes the OS gave for them.
+pub struct JsonPrinter<W> {
+    out: W,
+    path: Vec<u8>,
+    binary: bool,
+    matched_lines: usize,
+    matches: usize,
+}
+
+impl<W: Write> JsonPrinter<W> {
+    pub fn new(out: W) -> Self {
+        JsonPrinter {
+            out,
+            path: Vec::new(),
+            binary: false,
+            matched_lines: 0,
+            matches: 0,
+        }
+    }
+
+    fn line(&mut self, kind: &str, m: &Match) -> io::Result<()> {
+        let submatches: Vec<String> = m
+            .spans
+            .iter()
+            .enumerate()
+            .filter(|&(_, &(start, end))| start < end)
+            .map(|(i, &(start, end))| {
+                // only fuzzy matches have a distance worth mentioning
+                let distance = m
+                    .distances
+                    .get(i)
+                    .map_or(String::new(), |d| format!(r#","distance":{}"#, d));
+                format!(
+                    r#"{{"match":{},"start":{},"end":{}{}}}"#,
+                    data(&m.line[start..end]),
+                    start,
+                    end,
+                    distance
+                )
+            }).collect();
+        writeln!(
+            self.out,
+            r#"{{"type":"{}","data":{{"path":{},"lines":{},"line_number":{},"absolute_offset":{},"submatches":[{}]}}}}"#,
+            kind,
+            data(&self.path),
+            data(&m.line),
+            m.line_number,
+            m.byte_offset,
+            submatches.join(",")
+        )
+    }
+}
+
+impl<W: Write> Sink for JsonPrinter<W> {
+    fn begin(&mut self, path: &Path, _with_filename: bool) -> io::Result<()> {
+        self.path = path.as_os_str().as_encoded_bytes().to_vec();
+        self.binary = false;
+        self.matched_lines = 0;
+        self.matches = 0;
+        writeln!(
+            self.out,
+            r#"{{"type":"begin","data":{{"path":{}}}}}"#,
+            data(&self.path)
+        )
+    }
+
+    fn event(&mut self, event: Event) -> io::Result<()> {
+        match event {
+            Event::Match(ref m) => {
+                self.matched_lines += 1;
+                self.matches += m.spans.iter().filter(|&&(start, end)| start < end).count();
+                self.line("match", m)
+            }
+            Event::Context(ref m) => self.line("context", m),
+            Event::Binary => {
+                self.binary = true;
+                Ok(())
+            }
+        }
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        writeln!(
+            self.out,
+            r#"{{"type":"end","data":{{"path":{},"binary":{},"stats":{{"matched_lines":{},"matches":{}}}}}}}"#,
+            data(&self.path),
+            self.binary,
+            self.matched_lines,
+            self.matches
+        )
+    }
+}
+
+// `{"text": ...}` or `{"bytes": ...}`, whichever can hold `bytes` faithfully
+fn data(bytes: &[u8]) -> String {
+    match str::from_utf8(bytes) {
+        Ok(text) => format!(r#"{{"text":{}}}"#, string(text)),
+        Err(_) => format!(r#"{{"bytes":"{}"}}"#, base64(bytes)),
+    }
+}
+
+// `text` as a quoted JSON string
+fn string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// standard base64, with padding
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | ((b as u32) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frog() -> Match {
+        Match {
+            path: String::from("poem.txt"),
+            line_number: 7,
+            byte_offset: 137,
+            line: b"How public, like a frog".to_vec(),
+            spans: vec![(19, 23)],
+            distances: vec![],
+        }
+    }
+
+    fn print(events: Vec<Event>) -> String {
+        let mut out = Vec::new();
+        {
+            let mut printer = JsonPrinter::new(&mut out);
+            printer.begin(Path::new("poem.txt"), false).unwrap();
+            for event in events {
+                printer.event(event).unwrap();
+            }
+            printer.end().unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn writes_an_object_per_event() {
+        let context = Match {
+            line_number: 6,
+            byte_offset: 110,
+            line: b"How dreary to be somebody!".to_vec(),
+            spans: vec![],
+            ..frog()
+        };
+        assert_eq!(
+            print(vec![Event::Context(context), Event::Match(frog())]),
+            concat!(
+                r#"{"type":"begin","data":{"path":{"text":"poem.txt"}}}"#,
+                "\n",
+                r#"{"type":"context","data":{"path":{"text":"poem.txt"},"lines":{"text":"How dreary to be somebody!"},"line_number":6,"absolute_offset":110,"submatches":[]}}"#,
+                "\n",
+                r#"{"type":"match","data":{"path":{"text":"poem.txt"},"lines":{"text":"How public, like a frog"},"line_number":7,"absolute_offset":137,"submatches":[{"match":{"text":"frog"},"start":19,"end":23}]}}"#,
+                "\n",
+                r#"{"type":"end","data":{"path":{"text":"poem.txt"},"binary":false,"stats":{"matched_lines":1,"matches":1}}}"#,
+                "\n",
+            )
+        );
+    }
+
+    #[test]
+    fn includes_the_distance_of_fuzzy_matches() {
+        let fuzzy = Match {
+            line: b"How public, like a fog".to_vec(),
+            spans: vec![(19, 22)],
+            distances: vec![1],
+            ..frog()
+        };
+        assert!(print(vec![Event::Match(fuzzy)]).contains(
+            r#""submatches":[{"match":{"text":"fog"},"start":19,"end":22,"distance":1}]"#
+        ));
+    }
+
+    #[test]
+    fn escapes_quotes_and_control_characters() {
+        assert_eq!(
+            string("say \"hi\"\\\t\r\n\u{0}\u{1b}[1m\u{7f}é"),
+            r#""say \"hi\"\\\t\r\n\u0000\u001b[1m\u007fé""#
+        );
+    }
+
+    #[test]
+    fn writes_invalid_utf8_as_base64() {
+        assert_eq!(data(b"frog"), r#"{"text":"frog"}"#);
+        assert_eq!(data(b"\xff\xfe frog"), r#"{"bytes":"//4gZnJvZw=="}"#);
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+    }
+}
@@ -1,114 +1,701 @@
-use std::env;
+extern crate aho_corasick;
+extern crate encoding_rs;
+#[cfg(feature = "gzip")]
+extern crate flate2;
+extern crate regex;
+extern crate regex_syntax;
+extern crate unicode_normalization;
+
+mod args;
+// the integration tests' fixture helpers, for the unit tests too
+#[cfg(test)]
+#[path = "../tests/common/mod.rs"]
+mod common;
+mod decode;
+mod fold;
+mod fuzzy;
+mod gzip;
+mod ignore;
+mod index;
+mod json;
+mod matcher;
+mod pool;
+mod printer;
+mod replace;
+mod search;
+mod tail;
+mod tar;
+mod walk;
+
+pub use args::{ArgsError, USAGE};
+pub use encoding_rs::Encoding;
+pub use fold::Normalization;
+pub use matcher::{Matcher, MatcherKind, PatternError};
+pub use search::{Event, Match, Sink};
+
+use ignore::Rules;
+use index::{Index, Query};
+use json::JsonPrinter;
+use printer::Printer;
+use replace::DiffPrinter;
+use search::Searcher;
+use tail::Tail;
 use std::error::Error;
+use std::ffi::OsString;
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
+use std::io::BufReader;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use walk::Walk;
+
+/// The path that stands for standard input.
+pub const STDIN: &str = "-";
+
+// How often `--watch` checks files for new lines.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// What to print for each file searched.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum OutputMode {
+    /// Every selected line (the default)
+    #[default]
+    Lines,
+    /// Only the number of selected lines (`-c`)
+    Count,
+    /// Only the names of files with a selected line (`-l`)
+    FilesWithMatches,
+    /// Only the names of files without one (`-L`)
+    FilesWithoutMatch,
+    /// A JSON object for every file and line (`--json`)
+    Json,
+}
+
+/// When to highlight output with ANSI colors.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum ColorChoice {
+    /// Only when stdout is a terminal (the default)
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// What to do with files that look binary (have a NUL byte in their first
+/// block).
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum BinaryMode {
+    /// Search them, but print `Binary file X matches` instead of the lines
+    /// (the default)
+    #[default]
+    Summarize,
+    /// Search and print them like any other file (`--text`)
+    Text,
+    /// Don't search them at all (`--binary-skip`)
+    Skip,
+}
+
+/// The order to print files' results in.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum Sort {
+    /// Whichever file finishes first goes first (the default)
+    #[default]
+    Unsorted,
+    /// In the order the paths were given, then by name within directories
+    Path,
+}
+
+/// Whether to build or use trigram indexes of the directories searched.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum IndexMode {
+    /// Search without one (the default)
+    #[default]
+    Off,
+    /// Build or update the index of each path instead of searching it
+    /// (`minigrep_v2 index`)
+    Build,
+    /// Skip files that the index of the directory they're in shows can't
+    /// match (`--index`)
+    Use,
+}
 
 #[derive(Debug, PartialEq)]
 pub struct Config {
-    pub query: String,
-    pub path: String,
+    pub patterns: Vec<String>,
+    pub paths: Vec<String>,
     pub case_sensitive: bool,
+    pub case_fold: bool,
+    pub normalization: Option<Normalization>,
+    pub matcher: MatcherKind,
+    pub word_regexp: bool,
+    pub line_regexp: bool,
+    /// Match patterns as literals give or take this many characters
+    /// inserted, deleted or substituted
+    pub fuzzy: Option<usize>,
+    /// Match against whole files rather than a line at a time, so that
+    /// matches can span lines
+    pub multiline: bool,
+    pub follow_links: bool,
+    pub max_depth: Option<usize>,
+    pub hidden: bool,
+    pub no_ignore: bool,
+    /// Include (`*.rs`) and exclude (`!*.lock`) filters for paths found
+    /// while recursing
+    pub globs: Vec<String>,
+    /// How many files to search at once, with 0 meaning one per CPU
+    pub threads: usize,
+    pub sort: Sort,
+    pub index: IndexMode,
+    pub line_number: bool,
+    pub byte_offset: bool,
+    pub before_context: usize,
+    pub after_context: usize,
+    pub invert_match: bool,
+    /// Decompress files that turn out to be gzipped before searching them
+    pub search_zip: bool,
+    /// Search each regular file in tar archives as a file of its own
+    pub archives: bool,
+    pub binary: BinaryMode,
+    /// What to decode files without a byte order mark as, if not UTF-8
+    pub encoding: Option<&'static Encoding>,
+    /// What to replace matches with; when set, a diff of the changes is
+    /// printed instead of the usual output
+    pub replace: Option<String>,
+    /// Write replacements back to the files rather than printing a diff
+    pub in_place: bool,
+    pub output: OutputMode,
+    /// Keep following the files for lines added to them, rather than
+    /// searching them once
+    pub watch: bool,
+    /// `Never` unless set, unlike on the command line: `Auto` looks at
+    /// stdout, which needn't be where a library caller's output is going
+    pub color: ColorChoice,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            patterns: Vec::new(),
+            paths: Vec::new(),
+            case_sensitive: true,
+            case_fold: false,
+            normalization: None,
+            matcher: MatcherKind::Literal,
+            word_regexp: false,
+            line_regexp: false,
+            fuzzy: None,
+            multiline: false,
+            follow_links: false,
+            max_depth: None,
+            hidden: false,
+            no_ignore: false,
+            globs: Vec::new(),
+            threads: 0,
+            sort: Sort::Unsorted,
+            index: IndexMode::Off,
+            line_number: false,
+            byte_offset: false,
+            before_context: 0,
+            after_context: 0,
+            invert_match: false,
+            search_zip: false,
+            archives: false,
+            binary: BinaryMode::Summarize,
+            encoding: None,
+            replace: None,
+            in_place: false,
+            output: OutputMode::Lines,
+            watch: false,
+            color: ColorChoice::Never,
+        }
+    }
+}
+
+pub fn run(cfg: Config) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut out = Echo::default();
+    run_to(&cfg, &mut out)?;
+    Ok(out.lines)
+}
+
+/// Searches everything named by `cfg.paths`, writing results to `out` as
+/// they're found rather than holding on to them.
+pub fn run_to<W: Write>(cfg: &Config, out: W) -> Result<(), Box<dyn Error>> {
+    if cfg.index == IndexMode::Build {
+        return index_paths(cfg, out);
+    }
+    let matcher = Matcher::from_config(cfg)?;
+    match cfg.replace {
+        Some(ref replacement) if cfg.in_place => replace_paths(cfg, &matcher, replacement),
+        Some(ref replacement) => {
+            search_paths(cfg, &matcher, &mut DiffPrinter::new(&matcher, replacement, out))
+        }
+        None if cfg.watch => match cfg.output {
+            OutputMode::Json => watch_paths(cfg, &matcher, &mut JsonPrinter::new(out)),
+            _ => watch_paths(cfg, &matcher, &mut Printer::new(cfg, out)),
+        },
+        None => match cfg.output {
+            OutputMode::Json => search_paths(cfg, &matcher, &mut JsonPrinter::new(out)),
+            _ => search_paths(cfg, &matcher, &mut Printer::new(cfg, out)),
+        },
+    }
 }
 
-impl Config {
-    pub fn new(mut args: std::env::Args) -> Result<Self, &'static str> {
-        args.next();
+/// Searches everything named by `cfg.paths`, returning every matching line
+/// along with its position instead of printing it.
+pub fn find_matches(cfg: &Config) -> Result<Vec<Match>, Box<dyn Error>> {
+    let mut matches = Vec::new();
+    search_paths(cfg, &Matcher::from_config(cfg)?, &mut matches)?;
+    Ok(matches)
+}
+
+// Feeds whatever each file turns up to `sink`. One bad file shouldn't sink
+// the rest of the search, so failures are reported as they happen and only
+// turned into an error once everything else has been searched.
+fn search_paths<S: Sink>(
+    cfg: &Config,
+    matcher: &Matcher,
+    sink: &mut S,
+) -> Result<(), Box<dyn Error>> {
+    let searcher = Searcher::new(matcher, cfg);
+    let globs = Arc::new(Rules::globs(&cfg.globs)?);
+    let query = match cfg.index {
+        IndexMode::Use => Query::new(cfg, matcher).map(Arc::new),
+        _ => None,
+    };
+    let mut failures = 0;
+    let mut report = |searched: Result<(), Box<dyn Error>>| {
+        if let Err(e) = searched {
+            eprintln!("{}", e);
+            failures += 1;
+        }
+    };
+    let threads = match cfg.threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    // a single file has nothing to gain from the pool
+    let mut jobs = jobs(cfg, globs, query);
+    let first: Vec<_> = jobs.by_ref().take(2).collect();
+    let single = first.len() < 2;
+    let jobs = first.into_iter().chain(jobs);
+    if threads > 1 && !single {
+        let sorted = cfg.sort == Sort::Path;
+        pool::search(&searcher, jobs, threads, sorted, sink, report);
+    } else {
+        for job in jobs {
+            report(
+                job.map_err(Box::from)
+                    .and_then(|job| search_job(&searcher, &job, sink)),
+            );
+        }
+    }
+    if failures > 0 {
+        return Err(From::from(format!("{} file(s) could not be searched", failures)));
+    }
+    Ok(())
+}
+
+// Rewrites every file under `cfg.paths` with its matches replaced, carrying
+// on past any that can't be, like `search_paths`.
+fn replace_paths(cfg: &Config, matcher: &Matcher, replacement: &str) -> Result<(), Box<dyn Error>> {
+    let globs = Arc::new(Rules::globs(&cfg.globs)?);
+    let mut failures = 0;
+    for job in jobs(cfg, globs, None) {
+        let rewritten = job.and_then(|job| {
+            if job.path == Path::new(STDIN) {
+                return Err(io::Error::other("standard input can't be rewritten in place"));
+            }
+            replace::rewrite(matcher, replacement, cfg, &job.path)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", job.path.display(), e)))
+        });
+        if let Err(e) = rewritten {
+            eprintln!("{}", e);
+            failures += 1;
+        }
+    }
+    if failures > 0 {
+        return Err(From::from(format!("{} file(s) could not be rewritten", failures)));
+    }
+    Ok(())
+}
 
-        let query = match args.next() {
-            Some(arg) => arg,
-            None => return Err("No query string provided"),
+// Follows each file in `cfg.paths`, searching lines as they're added to it,
+// until the process is killed. Files that can't be read for the moment are
+// complained about once and then checked on quietly until they can be.
+fn watch_paths<S: Sink>(
+    cfg: &Config,
+    matcher: &Matcher,
+    sink: &mut S,
+) -> Result<(), Box<dyn Error>> {
+    let searcher = Searcher::new(matcher, cfg);
+    let with_filename = cfg.paths.len() > 1;
+    let mut tails = Vec::new();
+    for path in &cfg.paths {
+        if path == STDIN {
+            return Err(From::from("standard input can't be watched"));
+        }
+        if Path::new(path).is_dir() {
+            return Err(From::from(format!("{}: can't watch a directory", path)));
+        }
+        let failed = |e: io::Error| format!("{}: {}", path, e);
+        let tail = Tail::new(path).map_err(failed)?;
+        // how to read what's already there goes for what's added too
+        let progress = match tail.position() {
+            Some((line_number, byte_offset)) => {
+                let progress = searcher.start(&mut open(path).map_err(failed)?).map_err(failed)?;
+                Some(progress.at(line_number, byte_offset as usize))
+            }
+            None => None,
         };
+        tails.push((tail, progress));
+    }
+    let mut failing = vec![false; tails.len()];
+    loop {
+        for ((tail, progress), failing) in tails.iter_mut().zip(&mut failing) {
+            let name = tail.path().display().to_string();
+            let polled = match tail.poll() {
+                Ok(polled) => polled,
+                Err(e) => {
+                    if !*failing {
+                        eprintln!("{}: {}", name, e);
+                    }
+                    *failing = true;
+                    continue;
+                }
+            };
+            *failing = false;
+            for lines in polled {
+                let mut text = &lines.text[..];
+                // a file that's new, or has started over, is sniffed afresh
+                let progress = match *progress {
+                    Some(ref mut progress) if lines.byte_offset != 0 => progress,
+                    _ => {
+                        let started = searcher.start(&mut text)?;
+                        progress.insert(match lines.byte_offset {
+                            0 => started,
+                            at => started.at(lines.line_number, at as usize),
+                        })
+                    }
+                };
+                within_file(sink, tail.path(), with_filename, |sink| {
+                    searcher
+                        .resume(&name, progress, text, |event| sink.event(event))
+                        .map_err(Box::from)
+                })?;
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// Builds or updates the index of each directory in `cfg.paths`, carrying
+// on past any that can't be, like `search_paths`.
+fn index_paths<W: Write>(cfg: &Config, mut out: W) -> Result<(), Box<dyn Error>> {
+    let mut failures = 0;
+    for dir in cfg.paths.iter().map(Path::new) {
+        let updated = match dir.is_dir() {
+            true => Index::update(dir),
+            false => Err(io::Error::other("not a directory")),
+        };
+        match updated {
+            Ok(update) => writeln!(
+                out,
+                "{}: {} new, {} changed, {} removed, {} unchanged",
+                dir.display(),
+                update.added,
+                update.changed,
+                update.removed,
+                update.unchanged
+            )?,
+            Err(e) => {
+                eprintln!("{}: {}", dir.display(), e);
+                failures += 1;
+            }
+        }
+    }
+    if failures > 0 {
+        return Err(From::from(format!("{} directory(s) could not be indexed", failures)));
+    }
+    Ok(())
+}
+
+// A file (or standard input) to search.
+struct Job {
+    path: PathBuf,
+    with_filename: bool,
+    search_zip: bool,
+    archives: bool,
+    // set when an index shows that the file can't match, to whether it
+    // should still be reported as binary
+    unmatched: Option<bool>,
+}
 
-        let path = match args.next() {
-            Some(arg) => arg,
-            None => return Err("No filename provided"),
+// Every file under `cfg.paths` that isn't filtered out, in order. With a
+// `query`, files that the index of their root rules out are marked so.
+fn jobs<'a>(
+    cfg: &'a Config,
+    globs: Arc<Rules>,
+    query: Option<Arc<Query>>,
+) -> impl Iterator<Item = io::Result<Job>> + Send + 'a {
+    cfg.paths.iter().map(Path::new).flat_map(move |root| {
+        // grep's convention: name the file whenever more than one could match
+        let with_filename = cfg.paths.len() > 1 || root.is_dir();
+        let index = if cfg.index == IndexMode::Use && root.is_dir() {
+            match Index::load(root) {
+                Ok(index) => Some(index),
+                // the index only ever saves time, so do without
+                Err(e) => {
+                    eprintln!("{}; searching without it", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let query = query.clone();
+        let job = move |path: PathBuf| {
+            let unmatched = match (&index, &query) {
+                (Some(index), Some(query)) => index.rules_out(root, &path, query),
+                _ => None,
+            };
+            Job {
+                // replacing in a binary file is an error, which only searching
+                // it will bring up
+                unmatched: unmatched
+                    .filter(|&binary| !binary || cfg.replace.is_none())
+                    .map(|binary| binary && cfg.binary == BinaryMode::Summarize),
+                path,
+                with_filename,
+                search_zip: cfg.search_zip,
+                archives: cfg.archives,
+            }
+        };
+        let jobs: Box<dyn Iterator<Item = io::Result<Job>> + Send> = if root == Path::new(STDIN) {
+            Box::new(iter::once(Ok(job(root.to_path_buf()))))
+        } else {
+            let walk = Walk::new(root, cfg.follow_links, cfg.max_depth)
+                .hidden(cfg.hidden)
+                .ignore_files(!cfg.no_ignore)
+                .globs(globs.clone());
+            Box::new(walk.map(move |entry| entry.map(&job)))
         };
+        jobs
+    })
+}
 
-        let case_sensitive = env::var("CASE_INSENSITIVE").is_err();
+fn search_job<S: Sink>(searcher: &Searcher, job: &Job, sink: &mut S) -> Result<(), Box<dyn Error>> {
+    if let Some(binary) = job.unmatched {
+        // what searching it would turn up, without reading it
+        return within_file(sink, &job.path, job.with_filename, |sink| {
+            if binary {
+                sink.event(Event::Binary)?;
+            }
+            Ok(())
+        });
+    }
+    if job.path == Path::new(STDIN) {
+        let stdin = io::stdin();
+        let name = Path::new("(standard input)");
+        search_reader(searcher, name, stdin.lock(), job, sink)
+    } else {
+        search_file(searcher, job, sink)
+    }
+}
+
+fn search_file<S: Sink>(
+    searcher: &Searcher,
+    job: &Job,
+    sink: &mut S,
+) -> Result<(), Box<dyn Error>> {
+    let path = &job.path;
+    let reader = open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    search_reader(searcher, path, reader, job, sink)
+}
 
-        Ok(Config {
-            query,
-            path,
-            case_sensitive,
-        })
+fn search_reader<R: BufRead, S: Sink>(
+    searcher: &Searcher,
+    name: &Path,
+    reader: R,
+    job: &Job,
+    sink: &mut S,
+) -> Result<(), Box<dyn Error>> {
+    if job.search_zip {
+        let reader = gzip::decompressed(reader).map_err(|e| format!("{}: {}", name.display(), e))?;
+        search_contents(searcher, name, reader, job, sink)
+    } else {
+        search_contents(searcher, name, reader, job, sink)
     }
 }
 
-pub fn run(cfg: Config) -> Result<Vec<String>, Box<Error>> {
-    let contents = read_contents(cfg.path)?;
-    // TODO: clean this up with lamdas! (ch 13)
-    let results = if cfg.case_sensitive {
-        search(&cfg.query, &contents)
+fn search_contents<R: BufRead, S: Sink>(
+    searcher: &Searcher,
+    name: &Path,
+    reader: R,
+    job: &Job,
+    sink: &mut S,
+) -> Result<(), Box<dyn Error>> {
+    if job.archives {
+        let (archive, reader) = tar::sniff(reader).map_err(|e| format!("{}: {}", name.display(), e))?;
+        if archive {
+            search_archive(searcher, name, reader, sink)
+        } else {
+            search_text(searcher, name, reader, job.with_filename, sink)
+        }
     } else {
-        search_case_insensitive(&cfg.query, &contents)
-    };
-    for line in results.iter() {
-        println!("{}", line);
+        search_text(searcher, name, reader, job.with_filename, sink)
     }
-    Ok(results
-        .iter()
-        .map(|_str| _str.to_string())
-        .collect::<Vec<String>>())
 }
 
-fn read_contents(path: String) -> Result<String, Box<Error>> {
-    let mut contents = String::new();
-    File::open(path)?.read_to_string(&mut contents)?;
-    Ok(contents)
+fn search_text<R: BufRead, S: Sink>(
+    searcher: &Searcher,
+    name: &Path,
+    reader: R,
+    with_filename: bool,
+    sink: &mut S,
+) -> Result<(), Box<dyn Error>> {
+    let shown = name.display().to_string();
+    within_file(sink, name, with_filename, |sink| {
+        searcher
+            .search(&shown, reader, |event| sink.event(event))
+            .map_err(|e| Box::from(format!("{}: {}", shown, e)))
+    })
 }
 
-fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    contents
-        .lines()
-        .filter(|line| line.contains(query))
+// Searches every regular file in the archive as though it were a file
+// named `archive:member`. A corrupt archive is given up on, but only
+// after whatever members could be read have been searched.
+fn search_archive<R: BufRead, S: Sink>(
+    searcher: &Searcher,
+    name: &Path,
+    reader: R,
+    sink: &mut S,
+) -> Result<(), Box<dyn Error>> {
+    let mut archive = tar::Archive::new(reader);
+    while let Some(member) = archive
+        .next_member()
+        .map_err(|e| format!("{}: {}", name.display(), e))?
+    {
+        let mut name = OsString::from(name);
+        name.push(":");
+        name.push(&member.path);
+        search_text(searcher, Path::new(&name), BufReader::new(&mut archive), true, sink)?;
+    }
+    Ok(())
+}
+
+// Runs `search` between the sink's `begin` and `end` for `name`, ending it
+// even if the search fails so that no file is left open.
+fn within_file<S, F>(
+    sink: &mut S,
+    name: &Path,
+    with_filename: bool,
+    search: F,
+) -> Result<(), Box<dyn Error>>
+where
+    S: Sink,
+    F: FnOnce(&mut S) -> Result<(), Box<dyn Error>>,
+{
+    sink.begin(name, with_filename)?;
+    let searched = search(sink);
+    let ended = sink.end();
+    searched?;
+    ended?;
+    Ok(())
+}
+
+fn open<P: AsRef<Path>>(path: P) -> io::Result<BufReader<File>> {
+    File::open(path).map(BufReader::new)
+}
+
+// Prints output a line at a time through `println!` (so the test harness
+// can capture it) while keeping a copy of each line for `run` to return.
+#[derive(Default)]
+struct Echo {
+    partial: Vec<u8>,
+    lines: Vec<String>,
+}
+
+impl Write for Echo {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            if byte == b'\n' {
+                let line = String::from_utf8_lossy(&self.partial).into_owned();
+                println!("{}", line);
+                self.lines.push(line);
+                self.partial.clear();
+            } else {
+                self.partial.push(byte);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn search_with<'a>(matcher: &Matcher, contents: &'a str) -> Vec<&'a str> {
+    let mut matches = Vec::new();
+    Searcher::new(matcher, &Config::default())
+        .search("", contents.as_bytes(), |event| matches.event(event))
+        .unwrap();
+    matches
+        .iter()
+        .map(|m| &contents[m.byte_offset..m.byte_offset + m.line.len()])
         .collect()
 }
 
+#[cfg(test)]
+fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    let matcher = Matcher::new(query, MatcherKind::Literal, true).unwrap();
+    search_with(&matcher, contents)
+}
+
+#[cfg(test)]
 fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    let query = query.to_lowercase();
-    contents
-        .lines()
-        .filter(|l| l.to_lowercase().as_str().contains(query.as_str()))
-        .collect()
+    let matcher = Matcher::new(query, MatcherKind::Literal, false).unwrap();
+    search_with(&matcher, contents)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    // TODO: how to run these tests with `env::Args`
-    // internet doesn't seem to know:
-    // https://stackoverflow.com/questions/47441279/creating-an-stdenvargs-iterator-for-testing
-    // ^--- solutions at top don't work, solution in bottom dodges the question
-
-    // #[test]
-    // fn parses_configs_from_valid_args() {
-    //     assert_eq!(
-    //         Ok(Config {
-    //             query: "body".to_string(),
-    //             path: "poem.txt".to_string(),
-    //             case_sensitive: true,
-    //         }),
-    //         Config::new(std::env::Args([
-    //             "path/to/binary".to_string(),
-    //             "body".to_string(),
-    //             "poem.txt".to_string()
-    //         ]))
-    //     )
-    // }
-
-    // #[test]
-    // fn returns_err_from_invalid_args() {
-    //     assert_eq!(
-    //         Err("foo"),
-    //         Config::new(&["body".to_string(), "poem.txt".to_string()])
-    //     )
-    // }
+    #[test]
+    fn parses_configs_from_valid_args() {
+        assert_eq!(
+            Ok(Config {
+                patterns: vec!["body".to_string()],
+                paths: vec!["poem.txt".to_string()],
+                case_sensitive: true,
+                color: ColorChoice::Auto,
+                ..Config::default()
+            }),
+            // `parse`, unlike `new`, leaves CASE_INSENSITIVE out of it
+            Config::parse(vec!["body".to_string(), "poem.txt".to_string()])
+        )
+    }
+
+    #[test]
+    fn returns_err_from_invalid_args() {
+        assert_eq!(
+            Err(ArgsError::MissingQuery),
+            Config::parse(Vec::new())
+        )
+    }
 
     #[test]
     fn reads_contents_from_file() {
+        let mut contents = String::new();
+        open("poem.txt").unwrap().read_to_string(&mut contents).unwrap();
         assert_eq!(
-            read_contents("poem.txt".to_string()).unwrap(),
+            contents,
             "\
 I’m nobody! Who are you?
 Are you nobody, too?
@@ -150,4 +737,16 @@ Trust me.
         )
     }
 
+    #[test]
+    fn performs_regex_search() {
+        let matcher = Matcher::new(r"^[A-Z]\w+\.$", MatcherKind::Regex, true).unwrap();
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust.
+";
+        assert_eq!(search_with(&matcher, contents), vec!["Trust."])
+    }
+
 }
@@ -1,18 +1,27 @@
 extern crate minigrep_v2;
-use minigrep_v2::Config;
+use minigrep_v2::{ArgsError, Config};
 
 use std::env;
+use std::io;
 use std::process;
 
 fn main() {
     // let args: Vec<String> = env::args().collect();
 
-    let cfg = Config::new(env::args()).unwrap_or_else(|err| {
-        eprintln!("Could not parse arguments: {}", err);
-        process::exit(1);
+    let cfg = Config::new(env::args()).unwrap_or_else(|err| match err {
+        ArgsError::Help | ArgsError::Version => {
+            println!("{}", err);
+            process::exit(0);
+        }
+        _ => {
+            eprintln!("Could not parse arguments: {}", err);
+            eprintln!("Try `minigrep_v2 --help` for more information.");
+            process::exit(1);
+        }
     });
 
-    if let Err(e) = minigrep_v2::run(cfg) {
+    let stdout = io::stdout();
+    if let Err(e) = minigrep_v2::run_to(&cfg, stdout.lock()) {
         eprintln!("Application error: {}", e);
         process::exit(1);
     }
@@ -0,0 +1,767 @@
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder, MatchKind};
+use fold::Folder;
+use fuzzy::Fuzzy;
+use regex::bytes::{Regex, RegexBuilder};
+use regex_syntax::hir::literal::Extractor;
+use regex_syntax::hir::{
+    Capture, Class, ClassUnicode, ClassUnicodeRange, Hir, HirKind, Literal, Repetition,
+};
+use regex_syntax::ParserBuilder;
+use std::error::Error;
+use std::fmt;
+use std::str;
+use Config;
+
+/// How patterns should be interpreted.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum MatcherKind {
+    /// Patterns are matched as plain substrings (the default)
+    #[default]
+    Literal,
+    /// Patterns are regular expressions (anchors, classes, groups, ...)
+    Regex,
+}
+
+/// Returned when a pattern can't be compiled into a `Matcher`.
+#[derive(Debug)]
+pub struct PatternError {
+    pattern: String,
+    reason: String,
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid pattern `{}`: {}", self.pattern, self.reason)
+    }
+}
+
+impl Error for PatternError {}
+
+/// A compiled set of patterns that can be run against lines of input. A
+/// line matches if any one of the patterns does.
+#[derive(Debug)]
+pub struct Matcher {
+    engine: Engine,
+    // when set, lines are folded before matching and spans mapped back
+    folder: Option<Folder>,
+    // whether replacements can refer to capture groups
+    expand: bool,
+}
+
+#[derive(Debug)]
+enum Engine {
+    Regex(Regex),
+    // plain substrings, searched for all at once however many there are
+    Literals(AhoCorasick),
+    // substrings, give or take a few characters
+    Fuzzy(Fuzzy),
+}
+
+impl Matcher {
+    pub fn new(query: &str, kind: MatcherKind, case_sensitive: bool) -> Result<Self, PatternError> {
+        Matcher::from_config(&Config {
+            patterns: vec![query.to_string()],
+            matcher: kind,
+            case_sensitive,
+            ..Config::default()
+        })
+    }
+
+    /// Compiles `cfg.patterns` according to the matching options in `cfg`.
+    /// With no patterns at all, nothing matches.
+    ///
+    /// Fuzzy matching treats every pattern as a literal, and doesn't take
+    /// `word_regexp` or `line_regexp` into account.
+    pub fn from_config(cfg: &Config) -> Result<Self, PatternError> {
+        let folder = Folder {
+            case_fold: cfg.case_fold,
+            normalization: cfg.normalization,
+        };
+        let folder = if folder.case_fold || folder.normalization.is_some() {
+            Some(folder)
+        } else {
+            None
+        };
+        let patterns: Vec<String> = cfg
+            .patterns
+            .iter()
+            .map(|pattern| match (cfg.matcher, folder) {
+                (MatcherKind::Literal, Some(folder)) => folder.fold_str(pattern),
+                // folding the source of a regex would mangle escapes like
+                // `\W`, so fold what it matches instead
+                (MatcherKind::Regex, Some(folder)) => fold_regex(pattern, folder, cfg),
+                (_, None) => pattern.clone(),
+            }).collect();
+
+        if let Some(max_distance) = cfg.fuzzy {
+            // with case folding the text arrives folded already
+            let case_sensitive = cfg.case_sensitive || cfg.case_fold;
+            return Ok(Matcher {
+                engine: Engine::Fuzzy(Fuzzy::new(&patterns, max_distance, case_sensitive)),
+                folder,
+                expand: false,
+            });
+        }
+
+        // anything a plain substring search can't express goes to the regex
+        // engine, which gets us case-insensitivity and boundaries that
+        // preserve byte offsets for free
+        let literal = cfg.matcher == MatcherKind::Literal
+            && (cfg.case_sensitive || cfg.case_fold)
+            && !cfg.word_regexp
+            && !cfg.line_regexp;
+        if literal || patterns.is_empty() {
+            let literals = AhoCorasickBuilder::new()
+                .match_kind(MatchKind::LeftmostFirst)
+                .build(&patterns)
+                .map_err(|e| PatternError {
+                    pattern: cfg.patterns.join("\n"),
+                    reason: e.to_string(),
+                })?;
+            return Ok(Matcher {
+                engine: Engine::Literals(literals),
+                folder,
+                expand: false,
+            });
+        }
+
+        let pattern = patterns
+            .iter()
+            .map(|pattern| match cfg.matcher {
+                MatcherKind::Literal => format!("(?:{})", regex::escape(pattern)),
+                MatcherKind::Regex => format!("(?:{})", pattern),
+            }).collect::<Vec<_>>()
+            .join("|");
+        // the half boundaries only look outside the match, so `-w` still
+        // works for queries that start or end with punctuation
+        let pattern = if cfg.line_regexp {
+            format!("^(?:{})$", pattern)
+        } else if cfg.word_regexp {
+            format!(r"\b{{start-half}}(?:{})\b{{end-half}}", pattern)
+        } else {
+            pattern
+        };
+        let compile = |pattern: &str| {
+            RegexBuilder::new(pattern)
+                .case_insensitive(case_insensitive(cfg))
+                // over a whole file, `^` and `$` still mean a line's ends
+                .multi_line(cfg.multiline)
+                .crlf(cfg.multiline)
+                .build()
+        };
+        match compile(&pattern) {
+            Ok(regex) => Ok(Matcher {
+                engine: Engine::Regex(regex),
+                folder,
+                expand: cfg.matcher == MatcherKind::Regex,
+            }),
+            // blame the pattern that's actually broken, rather than the
+            // alternation of all of them that we built
+            Err(e) => Err(cfg
+                .patterns
+                .iter()
+                .zip(&patterns)
+                .filter_map(|(original, pattern)| {
+                    compile(pattern).err().map(|e| PatternError {
+                        pattern: original.clone(),
+                        reason: describe(e),
+                    })
+                }).next()
+                .unwrap_or_else(|| PatternError {
+                    pattern: cfg.patterns.join("\n"),
+                    reason: describe(e),
+                })),
+        }
+    }
+
+    /// Literals one of which every match starts with, for ruling out text
+    /// without searching it: text that contains none of them can't match.
+    /// `None` if there's no telling, or too many to be worth listing.
+    ///
+    /// `cfg` should be the one the matcher was built from.
+    pub fn prefixes(&self, cfg: &Config) -> Option<Vec<Vec<u8>>> {
+        // matches are found in folded text, which the literals would be of
+        if self.folder.is_some() {
+            return None;
+        }
+        match self.engine {
+            Engine::Literals(_) => {
+                Some(cfg.patterns.iter().map(|p| p.as_bytes().to_vec()).collect())
+            }
+            Engine::Regex(ref regex) => {
+                let hir = ParserBuilder::new()
+                    .case_insensitive(case_insensitive(cfg))
+                    .multi_line(cfg.multiline)
+                    .crlf(cfg.multiline)
+                    .utf8(false)
+                    .build()
+                    .parse(regex.as_str())
+                    .ok()?;
+                let prefixes = Extractor::new().extract(&hir);
+                let literals = prefixes.literals()?;
+                Some(literals.iter().map(|l| l.as_bytes().to_vec()).collect())
+            }
+            Engine::Fuzzy(_) => None,
+        }
+    }
+
+    pub fn is_match(&self, line: &[u8]) -> bool {
+        match self.folder {
+            Some(ref folder) => self.engine.is_match(&folder.fold(line).text),
+            None => self.engine.is_match(line),
+        }
+    }
+
+    /// The `(start, end)` byte ranges of every non-overlapping match in `line`.
+    pub fn find_all(&self, line: &[u8]) -> Vec<(usize, usize)> {
+        self.find_all_with_distances(line).0
+    }
+
+    /// Like `find_all`, along with how far each match is from the query
+    /// when matching fuzzily. The distances are empty otherwise.
+    pub fn find_all_with_distances(&self, line: &[u8]) -> (Vec<(usize, usize)>, Vec<usize>) {
+        let folded = self.folder.map(|folder| folder.fold(line));
+        let text = folded.as_ref().map_or(line, |folded| &folded.text[..]);
+        let (spans, distances): (Vec<_>, _) = match self.engine {
+            Engine::Fuzzy(ref fuzzy) => fuzzy
+                .find_all(text)
+                .into_iter()
+                .map(|(start, end, distance)| ((start, end), distance))
+                .unzip(),
+            _ => (self.engine.find_all(text), Vec::new()),
+        };
+        let spans = match folded {
+            Some(ref folded) => spans
+                .into_iter()
+                .map(|(start, end)| folded.original_span(start, end))
+                .collect(),
+            None => spans,
+        };
+        (spans, distances)
+    }
+
+    /// `line` with every match swapped for `replacement`.
+    ///
+    /// For regex patterns, `$1` and `$name` in `replacement` stand for what
+    /// that group matched (spelled `${1}` and `${name}` when more word
+    /// characters follow), and `$$` for a plain `$`. Groups that took no
+    /// part in the match come out empty. Literal patterns are replaced by
+    /// `replacement` exactly as it is.
+    pub fn replace_all(&self, line: &[u8], replacement: &str) -> Vec<u8> {
+        let folded = self.folder.map(|folder| folder.fold(line));
+        let text = folded.as_ref().map_or(line, |folded| &folded.text[..]);
+        let original = |start, end| match folded {
+            Some(ref folded) => folded.original_span(start, end),
+            None => (start, end),
+        };
+        let mut replaced = Vec::with_capacity(line.len());
+        let mut last = 0;
+        match self.engine {
+            Engine::Regex(ref regex) if self.expand => {
+                let template = parse_template(replacement);
+                for caps in regex.captures_iter(text) {
+                    let whole = caps.get(0).unwrap();
+                    let (start, end) = original(whole.start(), whole.end());
+                    // folding can leave two matches sharing a character of
+                    // the original; it goes to the first
+                    replaced.extend_from_slice(&line[last..start.max(last)]);
+                    for part in &template {
+                        let group = match *part {
+                            Part::Text(text) => {
+                                replaced.extend_from_slice(text.as_bytes());
+                                continue;
+                            }
+                            Part::Index(i) => caps.get(i),
+                            Part::Name(name) => caps.name(name),
+                        };
+                        if let Some(group) = group {
+                            let (start, end) = original(group.start(), group.end());
+                            replaced.extend_from_slice(&line[start..end]);
+                        }
+                    }
+                    last = end.max(last);
+                }
+            }
+            _ => {
+                for (start, end) in self.engine.find_all(text) {
+                    let (start, end) = original(start, end);
+                    replaced.extend_from_slice(&line[last..start.max(last)]);
+                    replaced.extend_from_slice(replacement.as_bytes());
+                    last = end.max(last);
+                }
+            }
+        }
+        replaced.extend_from_slice(&line[last..]);
+        replaced
+    }
+}
+
+// A piece of a replacement: text to copy as it is, or a group to fill in.
+#[derive(Debug, PartialEq)]
+enum Part<'a> {
+    Text(&'a str),
+    Index(usize),
+    Name(&'a str),
+}
+
+// Splits a replacement into parts, using the same syntax as the regex
+// crate's `Captures::expand`: a `$` that doesn't start a group reference
+// is left as it is.
+fn parse_template(template: &str) -> Vec<Part<'_>> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while let Some(i) = rest.find('$') {
+        if i > 0 {
+            parts.push(Part::Text(&rest[..i]));
+        }
+        rest = &rest[i + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            parts.push(Part::Text("$"));
+            rest = after;
+            continue;
+        }
+        let (name, after) = match rest.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => ("", rest),
+            },
+            None => {
+                let end = rest
+                    .find(|c: char| c != '_' && !c.is_ascii_alphanumeric())
+                    .unwrap_or(rest.len());
+                (&rest[..end], &rest[end..])
+            }
+        };
+        if name.is_empty() {
+            parts.push(Part::Text("$"));
+            continue;
+        }
+        parts.push(match name.parse() {
+            Ok(i) => Part::Index(i),
+            Err(_) => Part::Name(name),
+        });
+        rest = after;
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest));
+    }
+    parts
+}
+
+impl Engine {
+    fn is_match(&self, text: &[u8]) -> bool {
+        match *self {
+            Engine::Regex(ref regex) => regex.is_match(text),
+            Engine::Literals(ref literals) => literals.is_match(text),
+            Engine::Fuzzy(ref fuzzy) => fuzzy.is_match(text),
+        }
+    }
+
+    fn find_all(&self, text: &[u8]) -> Vec<(usize, usize)> {
+        match *self {
+            Engine::Regex(ref regex) => regex
+                .find_iter(text)
+                .map(|m| (m.start(), m.end()))
+                .collect(),
+            Engine::Literals(ref literals) => literals
+                .find_iter(text)
+                .map(|m| (m.start(), m.end()))
+                .collect(),
+            Engine::Fuzzy(ref fuzzy) => fuzzy
+                .find_all(text)
+                .into_iter()
+                .map(|(start, end, _)| (start, end))
+                .collect(),
+        }
+    }
+}
+
+// Whether the regex engine should ignore case: with -i, or when a regex has
+// to make up for its case not having been folded.
+// Classes with more characters than this are left as they are when folding
+// a regex, rather than listing what each of them folds to.
+const MAX_FOLDED_CLASS: usize = 256;
+
+// Rewrites a regex to match text that `folder` has been over: its literals
+// are folded the way the text is, and so are the characters of its classes
+// (`[ẞx]` becomes `ss|x`). A pattern that doesn't parse is left for
+// compiling it to complain about.
+fn fold_regex(pattern: &str, folder: Folder, cfg: &Config) -> String {
+    let hir = ParserBuilder::new()
+        .case_insensitive(case_insensitive(cfg))
+        .multi_line(cfg.multiline)
+        .crlf(cfg.multiline)
+        .build()
+        .parse(pattern);
+    match hir {
+        Ok(hir) => fold_hir(hir, folder).to_string(),
+        Err(_) => pattern.to_string(),
+    }
+}
+
+fn fold_hir(hir: Hir, folder: Folder) -> Hir {
+    let fold = |hir: Hir| fold_hir(hir, folder);
+    match hir.into_kind() {
+        HirKind::Empty => Hir::empty(),
+        HirKind::Literal(Literal(bytes)) => match str::from_utf8(&bytes) {
+            Ok(text) => Hir::literal(folder.fold_str(text).into_bytes()),
+            Err(_) => Hir::literal(bytes),
+        },
+        HirKind::Class(Class::Unicode(class)) => fold_class(class, folder),
+        HirKind::Class(class) => Hir::class(class),
+        HirKind::Look(look) => Hir::look(look),
+        HirKind::Repetition(repetition) => Hir::repetition(Repetition {
+            sub: Box::new(fold(*repetition.sub)),
+            ..repetition
+        }),
+        HirKind::Capture(capture) => Hir::capture(Capture {
+            sub: Box::new(fold(*capture.sub)),
+            ..capture
+        }),
+        HirKind::Concat(subs) => Hir::concat(subs.into_iter().map(fold).collect()),
+        HirKind::Alternation(subs) => Hir::alternation(subs.into_iter().map(fold).collect()),
+    }
+}
+
+fn fold_class(class: ClassUnicode, folder: Folder) -> Hir {
+    let size: usize = class
+        .iter()
+        .map(|range| range.end() as usize - range.start() as usize + 1)
+        .sum();
+    if size > MAX_FOLDED_CLASS {
+        return Hir::class(Class::Unicode(class));
+    }
+    // characters that fold to one character stay a class, and the rest
+    // become alternatives to it
+    let mut chars = Vec::new();
+    let mut strings = Vec::new();
+    for c in class.iter().flat_map(|range| range.start()..=range.end()) {
+        let folded = folder.fold_str(c.encode_utf8(&mut [0; 4]));
+        let mut folded_chars = folded.chars();
+        match (folded_chars.next(), folded_chars.next()) {
+            (Some(c), None) => chars.push(ClassUnicodeRange::new(c, c)),
+            _ => strings.push(folded),
+        }
+    }
+    strings.sort();
+    strings.dedup();
+    let mut alternatives = Vec::new();
+    if !chars.is_empty() {
+        alternatives.push(Hir::class(Class::Unicode(ClassUnicode::new(chars))));
+    }
+    alternatives.extend(strings.into_iter().map(|s| Hir::literal(s.into_bytes())));
+    Hir::alternation(alternatives)
+}
+
+fn case_insensitive(cfg: &Config) -> bool {
+    !cfg.case_sensitive || (cfg.case_fold && cfg.matcher == MatcherKind::Regex)
+}
+
+// `regex::Error` renders a multi-line report with the pattern repeated in it;
+// keep only the final `error: ...` line since we print the pattern ourselves
+pub fn describe(e: regex::Error) -> String {
+    match e {
+        regex::Error::Syntax(ref report) => report
+            .lines()
+            .last()
+            .map(|l| l.trim_start_matches("error: ").to_string())
+            .unwrap_or_else(|| report.clone()),
+        regex::Error::CompiledTooBig(limit) => {
+            format!("compiled pattern exceeds size limit of {} bytes", limit)
+        }
+        _ => e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fold::Normalization;
+
+    #[test]
+    fn literal_queries_are_not_treated_as_regexes() {
+        let m = Matcher::new("a.c", MatcherKind::Literal, true).unwrap();
+        assert!(m.is_match(b"xa.cx"));
+        assert!(!m.is_match(b"abc"));
+    }
+
+    #[test]
+    fn regex_queries_support_anchors_classes_and_groups() {
+        let m = Matcher::new(r"^To .*day$", MatcherKind::Regex, true).unwrap();
+        assert!(m.is_match(b"To tell your name the livelong day"));
+        assert!(!m.is_match(b"To an admiring bog!"));
+
+        let m = Matcher::new(r"\bnobody\b", MatcherKind::Regex, true).unwrap();
+        assert!(m.is_match(b"Are you nobody, too?"));
+        assert!(!m.is_match(b"How dreary to be somebody!"));
+
+        let m = Matcher::new(r"(frog|bog)!?$", MatcherKind::Regex, true).unwrap();
+        assert!(m.is_match(b"How public, like a frog"));
+        assert!(m.is_match(b"To an admiring bog!"));
+
+        let m = Matcher::new(r"[Tt]o{2,}", MatcherKind::Regex, true).unwrap();
+        assert!(m.is_match(b"Are you nobody, too?"));
+        assert!(!m.is_match(b"How dreary to be somebody!"));
+    }
+
+    #[test]
+    fn regex_queries_respect_case_sensitivity() {
+        let m = Matcher::new(r"^to\b", MatcherKind::Regex, false).unwrap();
+        assert!(m.is_match(b"To an admiring bog!"));
+    }
+
+    #[test]
+    fn finds_every_occurrence_in_a_line() {
+        let m = Matcher::new("TO", MatcherKind::Literal, false).unwrap();
+        assert_eq!(
+            m.find_all("To tell your name... to".as_bytes()),
+            vec![(0, 2), (21, 23)]
+        );
+    }
+
+    #[test]
+    fn matches_whole_words_only_when_asked() {
+        let words = |query: &str, kind, case_sensitive| {
+            Matcher::from_config(&Config {
+                patterns: vec![query.to_string()],
+                matcher: kind,
+                case_sensitive,
+                word_regexp: true,
+                ..Config::default()
+            }).unwrap()
+        };
+        let m = words("to", MatcherKind::Literal, true);
+        assert!(m.is_match(b"How dreary to be somebody!"));
+        assert!(!m.is_match(b"tomorrow, potato"));
+        assert_eq!(m.find_all(b"to potato to"), vec![(0, 2), (10, 12)]);
+
+        let m = words("TO", MatcherKind::Literal, false);
+        assert!(m.is_match(b"To an admiring bog!"));
+
+        // boundaries are Unicode-aware, and punctuation at the edges of the
+        // query doesn't need a word character next to it
+        let m = words("café", MatcherKind::Literal, true);
+        assert!(!m.is_match("cafés".as_bytes()));
+        assert!(m.is_match("un café!".as_bytes()));
+        let m = words("nobody!", MatcherKind::Literal, true);
+        assert!(m.is_match("I’m nobody! Who are you?".as_bytes()));
+
+        // every alternative has to be a whole word, not just the outer ones
+        let m = words("frog|bo", MatcherKind::Regex, true);
+        assert!(m.is_match(b"like a frog"));
+        assert!(!m.is_match(b"an admiring bog!"));
+    }
+
+    #[test]
+    fn matches_whole_lines_only_when_asked() {
+        let lines = |query: &str, kind| {
+            Matcher::from_config(&Config {
+                patterns: vec![query.to_string()],
+                matcher: kind,
+                case_sensitive: false,
+                line_regexp: true,
+                // `-x` wins over `-w`, as in grep
+                word_regexp: true,
+                ..Config::default()
+            }).unwrap()
+        };
+        let m = lines("how public, like a frog", MatcherKind::Literal);
+        assert_eq!(m.find_all(b"How public, like a frog"), vec![(0, 23)]);
+        assert!(!m.is_match(b"How public, like a frog!"));
+
+        let m = lines("to .*|.*bog!", MatcherKind::Regex);
+        assert!(m.is_match(b"To tell your name the livelong day"));
+        assert!(m.is_match(b"To an admiring bog!"));
+        assert!(!m.is_match(b"How dreary to be somebody!"));
+    }
+
+    #[test]
+    fn folds_case_and_normalizes_when_asked() {
+        let m = Matcher::from_config(&Config {
+            patterns: vec![String::from("STRASSE CAFÉ")],
+            case_fold: true,
+            normalization: Some(Normalization::Nfc),
+            ..Config::default()
+        }).unwrap();
+        let line = "an der Straße cafe\u{301}!";
+        assert_eq!(m.find_all(line.as_bytes()), vec![(7, 21)]);
+        assert_eq!(&line[7..21], "Straße cafe\u{301}");
+
+        let m = Matcher::from_config(&Config {
+            patterns: vec![String::from("I'm nobody")],
+            case_fold: true,
+            ..Config::default()
+        }).unwrap();
+        assert!(m.is_match("I’m nobody! Who are you?".as_bytes()));
+    }
+
+    #[test]
+    fn folds_regex_input_without_mangling_the_pattern() {
+        let m = Matcher::from_config(&Config {
+            patterns: vec![String::from(r"^\w+SSE\W")],
+            matcher: MatcherKind::Regex,
+            case_fold: true,
+            ..Config::default()
+        }).unwrap();
+        assert_eq!(m.find_all("Straße!".as_bytes()), vec![(0, 8)]);
+
+        let fold = |pattern: &str| {
+            Matcher::from_config(&Config {
+                patterns: vec![pattern.to_string()],
+                matcher: MatcherKind::Regex,
+                case_fold: true,
+                ..Config::default()
+            }).unwrap()
+        };
+        // literals and classes are folded just as the text is
+        assert!(fold("don’t").is_match("I don't know".as_bytes()));
+        assert!(fold("don't|won’t").is_match("I don’t know".as_bytes()));
+        assert_eq!(fold(r"Straße\b").find_all("an der STRASSE".as_bytes()), vec![(7, 14)]);
+        assert_eq!(fold(r"Stra[ßx]e").find_all("an der Straße".as_bytes()), vec![(7, 14)]);
+        assert_eq!(fold(r"(?P<s>Stra(ß)e)").find_all("straẞe".as_bytes()), vec![(0, 8)]);
+    }
+
+    fn any_of(patterns: &[&str], kind: MatcherKind, case_sensitive: bool) -> Matcher {
+        Matcher::from_config(&Config {
+            patterns: patterns.iter().map(|p| p.to_string()).collect(),
+            matcher: kind,
+            case_sensitive,
+            ..Config::default()
+        }).unwrap()
+    }
+
+    #[test]
+    fn matches_lines_containing_any_of_several_patterns() {
+        let line = b"How public, like a frog... to an admiring bog!";
+        let m = any_of(&["bog", "frog", "toad"], MatcherKind::Literal, true);
+        assert_eq!(m.find_all(line), vec![(19, 23), (42, 45)]);
+        assert!(!m.is_match(b"I'm nobody! Who are you?"));
+
+        let m = any_of(&["BOG", "Frog"], MatcherKind::Literal, false);
+        assert_eq!(m.find_all(line), vec![(19, 23), (42, 45)]);
+
+        let m = any_of(&[r"^how\b", r"b.g!$"], MatcherKind::Regex, false);
+        assert_eq!(m.find_all(line), vec![(0, 3), (42, 46)]);
+    }
+
+    #[test]
+    fn searches_for_thousands_of_literals_at_once() {
+        let denylist: Vec<String> = (0..2000).map(|n| format!("word{:04}", n)).collect();
+        let denylist: Vec<&str> = denylist.iter().map(|w| w.as_str()).collect();
+        let m = any_of(&denylist, MatcherKind::Literal, true);
+        assert_eq!(m.find_all(b"a word1999 and a word0042"), vec![(2, 10), (17, 25)]);
+        assert!(!m.is_match(b"word2000"));
+    }
+
+    #[test]
+    fn matches_nothing_without_patterns() {
+        let m = any_of(&[], MatcherKind::Regex, false);
+        assert!(!m.is_match(b"anything at all"));
+        assert!(!m.is_match(b""));
+    }
+
+    #[test]
+    fn reports_patterns_that_fail_to_compile() {
+        let err = Matcher::new("(frog", MatcherKind::Regex, true).unwrap_err();
+        assert_eq!(err.to_string(), "invalid pattern `(frog`: unclosed group");
+
+        // with several patterns, the error names the broken one
+        let err = Matcher::from_config(&Config {
+            patterns: vec![String::from("frog"), String::from("b[o"), String::from("toad")],
+            matcher: MatcherKind::Regex,
+            ..Config::default()
+        }).unwrap_err();
+        assert_eq!(err.to_string(), "invalid pattern `b[o`: unclosed character class");
+    }
+
+    #[test]
+    fn replaces_literals_as_they_are() {
+        let m = Matcher::new("FROG", MatcherKind::Literal, false).unwrap();
+        assert_eq!(m.replace_all(b"frog, Frog, fog", "$1 toad"), b"$1 toad, $1 toad, fog");
+    }
+
+    #[test]
+    fn expands_capture_groups_in_regex_replacements() {
+        let m = Matcher::new(r"(\w+)@(?P<host>\w+)", MatcherKind::Regex, true).unwrap();
+        assert_eq!(
+            m.replace_all(b"mail emily@amherst or ned@home", "${host}:$1 ($$2, $)"),
+            b"mail amherst:emily ($2, $) or home:ned ($2, $)".to_vec()
+        );
+        // unknown and unset groups are empty
+        assert_eq!(m.replace_all(b"a@b", "[$3$nope$1_]"), b"[]".to_vec());
+    }
+
+    #[test]
+    fn replaces_the_original_text_of_folded_matches() {
+        let m = Matcher::from_config(&Config {
+            patterns: vec![String::from("(stra)sse")],
+            matcher: MatcherKind::Regex,
+            case_fold: true,
+            ..Config::default()
+        }).unwrap();
+        assert_eq!(
+            m.replace_all("an der Straße!".as_bytes(), "$1ße"),
+            "an der Straße!".as_bytes()
+        );
+        assert_eq!(
+            m.replace_all("an der STRASSE!".as_bytes(), "<$1>"),
+            "an der <STRA>!".as_bytes()
+        );
+    }
+
+    #[test]
+    fn lists_the_prefixes_every_match_starts_with() {
+        let prefixes = |cfg: Config| {
+            let prefixes = Matcher::from_config(&cfg).unwrap().prefixes(&cfg)?;
+            let mut prefixes: Vec<_> = prefixes
+                .into_iter()
+                .map(|p| String::from_utf8(p).unwrap())
+                .collect();
+            prefixes.sort();
+            Some(prefixes)
+        };
+        let cfg = |patterns: &[&str], matcher| Config {
+            patterns: patterns.iter().map(|p| p.to_string()).collect(),
+            matcher,
+            ..Config::default()
+        };
+        let literal = cfg(&["frog", "bog"], MatcherKind::Literal);
+        assert_eq!(prefixes(literal), Some(vec![String::from("bog"), String::from("frog")]));
+        let regex = cfg(&[r"(fr|b)og\w*"], MatcherKind::Regex);
+        assert_eq!(prefixes(regex), Some(vec![String::from("bog"), String::from("frog")]));
+        // with -i, that means every way of writing the query
+        let prefixes_i = prefixes(Config {
+            case_sensitive: false,
+            ..cfg(&["ok"], MatcherKind::Literal)
+        }).unwrap();
+        assert_eq!(prefixes_i.len(), 6);
+        assert!(prefixes_i.contains(&String::from("o\u{212a}")));
+
+        assert_eq!(prefixes(cfg(&[r"\w+og"], MatcherKind::Regex)), None);
+        assert_eq!(
+            prefixes(Config {
+                case_fold: true,
+                ..cfg(&["frog"], MatcherKind::Literal)
+            }),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_replacement_templates() {
+        assert_eq!(
+            parse_template("a$1b${2}c$name-${x y}$$$"),
+            vec![
+                Part::Text("a"),
+                Part::Name("1b"),
+                Part::Index(2),
+                Part::Text("c"),
+                Part::Name("name"),
+                Part::Text("-"),
+                Part::Name("x y"),
+                Part::Text("$"),
+                Part::Text("$"),
+            ]
+        );
+    }
+}
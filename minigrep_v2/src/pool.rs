@@ -0,0 +1,177 @@
+use search::{Event, Searcher, Sink};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+use {search_job, Job};
+
+// What a worker sends back about the `n`th job: each thing its search sent
+// to its sink, and then how it went. Errors travel as text since boxed
+// errors can't cross threads.
+enum Message {
+    Begin(PathBuf, bool),
+    Event(Event),
+    End,
+    Done(Result<(), String>),
+}
+
+// Passes whatever a search sends its sink on to the calling thread.
+struct Forward<'a> {
+    n: usize,
+    tx: &'a mpsc::Sender<(usize, Message)>,
+}
+
+impl<'a> Forward<'a> {
+    fn send(&self, message: Message) -> io::Result<()> {
+        self.tx
+            .send((self.n, message))
+            .map_err(|_| io::Error::other("search abandoned"))
+    }
+}
+
+impl<'a> Sink for Forward<'a> {
+    fn begin(&mut self, path: &Path, with_filename: bool) -> io::Result<()> {
+        self.send(Message::Begin(path.to_path_buf(), with_filename))
+    }
+
+    fn event(&mut self, event: Event) -> io::Result<()> {
+        self.send(Message::Event(event))
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        self.send(Message::End)
+    }
+}
+
+/// Searches `jobs` on `threads` worker threads, handing each file's results
+/// to `sink` without mixing them with any other file's: whichever file is
+/// first to send anything (or the earliest, when `sorted`) is passed on as
+/// it's searched, while the others are held back until it's done. Failures
+/// go to `report` along with them.
+///
+/// `sink` itself is only ever used from the calling thread.
+pub fn search<I, S, F>(
+    searcher: &Searcher,
+    jobs: I,
+    threads: usize,
+    sorted: bool,
+    sink: &mut S,
+    mut report: F,
+) where
+    I: Iterator<Item = io::Result<Job>> + Send,
+    S: Sink,
+    F: FnMut(Result<(), Box<dyn Error>>),
+{
+    let (job_tx, job_rx) = mpsc::sync_channel::<(usize, Job)>(threads);
+    let job_rx = Mutex::new(job_rx);
+    let (done_tx, done_rx) = mpsc::channel::<(usize, Message)>();
+
+    thread::scope(|scope| {
+        let walk_done_tx = done_tx.clone();
+        scope.spawn(move || {
+            for (n, job) in jobs.enumerate() {
+                let sent = match job {
+                    Ok(job) => job_tx.send((n, job)).is_ok(),
+                    Err(e) => walk_done_tx
+                        .send((n, Message::Done(Err(e.to_string()))))
+                        .is_ok(),
+                };
+                if !sent {
+                    return;
+                }
+            }
+        });
+        for _ in 0..threads {
+            let done_tx = done_tx.clone();
+            let job_rx = &job_rx;
+            scope.spawn(move || loop {
+                // the lock is only held while waiting for a job, not while
+                // searching it
+                let next = job_rx.lock().unwrap().recv();
+                let (n, job) = match next {
+                    Ok(next) => next,
+                    Err(_) => return,
+                };
+                let mut forward = Forward { n, tx: &done_tx };
+                let searched = search_job(searcher, &job, &mut forward).map_err(|e| e.to_string());
+                if forward.send(Message::Done(searched)).is_err() {
+                    return;
+                }
+            });
+        }
+        // only the threads' copies are left, so the loop below ends when
+        // they all have
+        drop(done_tx);
+
+        // once the sink fails, the rest of that job is dropped and the
+        // failure reported along with it
+        let mut failed = None;
+        let mut deliver = |message: Message| {
+            let delivered = match message {
+                _ if failed.is_some() && !matches!(message, Message::Done(_)) => Ok(()),
+                Message::Begin(path, with_filename) => sink.begin(&path, with_filename),
+                Message::Event(event) => sink.event(event),
+                Message::End => sink.end(),
+                Message::Done(searched) => {
+                    if let Some(e) = failed.take() {
+                        report(Err(Box::from(e)));
+                    }
+                    return report(searched.map_err(Box::from));
+                }
+            };
+            if let Err(e) = delivered {
+                failed = Some(e);
+            }
+        };
+        // the job being passed on as it goes, and what the others have sent
+        // while waiting their turn
+        let mut streaming = None;
+        let mut held: BTreeMap<usize, Vec<Message>> = BTreeMap::new();
+        // the job whose turn it is, when sorted
+        let mut next = 0;
+        for (n, message) in done_rx {
+            if streaming.is_none() && (!sorted || n == next) {
+                streaming = Some(n);
+            }
+            if streaming != Some(n) {
+                held.entry(n).or_default().push(message);
+                continue;
+            }
+            let done = matches!(message, Message::Done(_));
+            deliver(message);
+            if !done {
+                continue;
+            }
+            // on to the next job: replay what it has sent so far, and carry
+            // on passing it through if it isn't finished yet
+            streaming = None;
+            next += 1;
+            loop {
+                let turn = if sorted {
+                    Some(next).filter(|n| held.contains_key(n))
+                } else {
+                    // finished ones first, since they won't hold anyone up
+                    let finished = held
+                        .iter()
+                        .find(|(_, messages)| matches!(messages.last(), Some(Message::Done(_))));
+                    finished.or_else(|| held.iter().next()).map(|(&n, _)| n)
+                };
+                let n = match turn {
+                    Some(n) => n,
+                    None => break,
+                };
+                let messages = held.remove(&n).unwrap_or_default();
+                let done = matches!(messages.last(), Some(Message::Done(_)));
+                messages.into_iter().for_each(&mut deliver);
+                if !done {
+                    streaming = Some(n);
+                    break;
+                }
+                next += 1;
+            }
+        }
+    });
+}
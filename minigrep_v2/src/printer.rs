@@ -0,0 +1,337 @@
+use search::{Event, Sink};
+use std::io;
+use std::io::prelude::*;
+use std::io::IsTerminal;
+use std::path::Path;
+use {ColorChoice, Config, OutputMode};
+
+// SGR escape sequences, using the same palette as GNU grep
+const MATCH: &str = "\x1b[1;31m";
+const PATH: &str = "\x1b[35m";
+const LINE_NUMBER: &str = "\x1b[32m";
+const BYTE_OFFSET: &str = "\x1b[33m";
+const SEPARATOR: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+/// Writes search results to `out` as they arrive, grep style.
+pub struct Printer<W> {
+    out: W,
+    output: OutputMode,
+    color: bool,
+    line_number: bool,
+    byte_offset: bool,
+    context: bool,
+    // the file being searched, and how many lines it has had selected
+    path: String,
+    with_filename: bool,
+    binary: bool,
+    selected: usize,
+    // where the last printed line came from, to tell when groups of
+    // context need a `--` between them
+    last: Option<(String, usize)>,
+}
+
+impl<W: Write> Printer<W> {
+    pub fn new(cfg: &Config, out: W) -> Self {
+        Printer {
+            out,
+            output: cfg.output,
+            color: match cfg.color {
+                ColorChoice::Always => true,
+                ColorChoice::Never => false,
+                ColorChoice::Auto => io::stdout().is_terminal(),
+            },
+            line_number: cfg.line_number,
+            byte_offset: cfg.byte_offset,
+            context: cfg.before_context > 0 || cfg.after_context > 0,
+            path: String::new(),
+            with_filename: false,
+            binary: false,
+            selected: 0,
+            last: None,
+        }
+    }
+
+    /// Writes `event` as `[path:][line number:][byte offset:]line`, using
+    /// `-` instead of `:` for context lines, preceded by a `--` separator
+    /// if it doesn't directly follow the last line printed. A multiline
+    /// match is written a line at a time, each with its own prefix.
+    fn print(&mut self, event: &Event) -> io::Result<()> {
+        let (m, sep) = match *event {
+            Event::Match(ref m) => (m, ':'),
+            Event::Context(ref m) => (m, '-'),
+            Event::Binary => return Ok(()),
+        };
+        let sep = sep.to_string();
+        // where the current line starts in `m.line`
+        let mut start = 0;
+        for (i, line) in m.line.split(|&b| b == b'\n').enumerate() {
+            let line_number = m.line_number + i;
+            if self.context {
+                if let Some((ref path, last)) = self.last {
+                    if *path != m.path || last + 1 != line_number {
+                        self.paint(SEPARATOR, "--")?;
+                        self.out.write_all(b"\n")?;
+                    }
+                }
+                self.last = Some((m.path.clone(), line_number));
+            }
+            if self.with_filename {
+                self.paint(PATH, &m.path)?;
+                self.paint(SEPARATOR, &sep)?;
+            }
+            if self.line_number {
+                self.paint(LINE_NUMBER, &line_number.to_string())?;
+                self.paint(SEPARATOR, &sep)?;
+            }
+            if self.byte_offset {
+                // exact unless the file was transcoded, which only the
+                // first line's offset allows for
+                self.paint(BYTE_OFFSET, &(m.byte_offset + start).to_string())?;
+                self.paint(SEPARATOR, &sep)?;
+            }
+            let end = start + line.len();
+            // lines other than the last keep their terminators, `\r\n` too
+            let line = match end < m.line.len() {
+                true => line.strip_suffix(b"\r").unwrap_or(line),
+                false => line,
+            };
+            match *event {
+                Event::Match(_) if self.color => {
+                    let mut written = 0;
+                    for &(span_start, span_end) in &m.spans {
+                        // just the part of the span on this line
+                        let span_start = span_start.max(start) - start;
+                        let span_end = span_end.min(end).saturating_sub(start).min(line.len());
+                        if span_start >= span_end {
+                            continue;
+                        }
+                        self.out.write_all(&line[written..span_start])?;
+                        write!(self.out, "{}", MATCH)?;
+                        self.out.write_all(&line[span_start..span_end])?;
+                        write!(self.out, "{}", RESET)?;
+                        written = span_end;
+                    }
+                    self.out.write_all(&line[written..])?;
+                }
+                _ => self.out.write_all(line)?,
+            }
+            self.out.write_all(b"\n")?;
+            start = end + 1;
+        }
+        Ok(())
+    }
+
+    fn print_path(&mut self) -> io::Result<()> {
+        let path = self.path.clone();
+        self.paint(PATH, &path)?;
+        self.out.write_all(b"\n")
+    }
+
+    fn paint(&mut self, color: &str, text: &str) -> io::Result<()> {
+        if self.color {
+            write!(self.out, "{}{}{}", color, text, RESET)
+        } else {
+            write!(self.out, "{}", text)
+        }
+    }
+}
+
+impl<W: Write> Sink for Printer<W> {
+    fn begin(&mut self, path: &Path, with_filename: bool) -> io::Result<()> {
+        self.path = path.display().to_string();
+        self.with_filename = with_filename;
+        self.binary = false;
+        self.selected = 0;
+        Ok(())
+    }
+
+    fn event(&mut self, event: Event) -> io::Result<()> {
+        match event {
+            Event::Match(_) => self.selected += 1,
+            Event::Context(_) => {}
+            Event::Binary => self.binary = true,
+        }
+        match (self.output, &event) {
+            // binary lines aren't fit to print, so just say that there were some
+            (OutputMode::Lines, &Event::Match(_)) if self.binary => match self.selected {
+                1 => writeln!(self.out, "Binary file {} matches", self.path),
+                _ => Ok(()),
+            },
+            (OutputMode::Lines, _) if self.binary => Ok(()),
+            (OutputMode::Lines, _) => self.print(&event),
+            _ => Ok(()),
+        }
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        match self.output {
+            // JSON is left to `JsonPrinter`
+            OutputMode::Lines | OutputMode::Json => Ok(()),
+            OutputMode::Count => {
+                if self.with_filename {
+                    let path = self.path.clone();
+                    self.paint(PATH, &path)?;
+                    self.paint(SEPARATOR, ":")?;
+                }
+                writeln!(self.out, "{}", self.selected)
+            }
+            OutputMode::FilesWithMatches if self.selected > 0 => self.print_path(),
+            OutputMode::FilesWithoutMatch if self.selected == 0 => self.print_path(),
+            OutputMode::FilesWithMatches | OutputMode::FilesWithoutMatch => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use search::Match;
+
+    fn line(line_number: usize, line: &str) -> Match {
+        Match {
+            path: String::from("poem.txt"),
+            line_number,
+            byte_offset: 137,
+            line: line.as_bytes().to_vec(),
+            spans: vec![],
+            distances: vec![],
+        }
+    }
+
+    fn frog() -> Event {
+        Event::Match(Match {
+            spans: vec![(19, 23)],
+            ..line(7, "How public, like a frog")
+        })
+    }
+
+    fn print(cfg: &Config, with_filename: bool, events: &[Event]) -> String {
+        let mut out = Vec::new();
+        {
+            let mut printer = Printer::new(cfg, &mut out);
+            printer.begin(Path::new("poem.txt"), with_filename).unwrap();
+            for event in events {
+                printer.event(event.clone()).unwrap();
+            }
+            printer.end().unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn prefixes_only_what_was_asked_for() {
+        let cfg = Config::default();
+        assert_eq!(print(&cfg, false, &[frog()]), "How public, like a frog\n");
+        assert_eq!(print(&cfg, true, &[frog()]), "poem.txt:How public, like a frog\n");
+    }
+
+    #[test]
+    fn prefixes_line_numbers_then_byte_offsets() {
+        let cfg = Config {
+            line_number: true,
+            byte_offset: true,
+            ..Config::default()
+        };
+        assert_eq!(
+            print(&cfg, true, &[Event::Context(line(6, "How dreary")), frog()]),
+            "poem.txt-6-137-How dreary\npoem.txt:7:137:How public, like a frog\n"
+        );
+    }
+
+    #[test]
+    fn separates_non_adjacent_groups_of_context() {
+        let cfg = Config {
+            line_number: true,
+            after_context: 1,
+            ..Config::default()
+        };
+        let events = [
+            Event::Match(line(1, "a")),
+            Event::Context(line(2, "b")),
+            Event::Match(line(4, "c")),
+            Event::Match(line(5, "d")),
+        ];
+        assert_eq!(print(&cfg, false, &events), "1:a\n2-b\n--\n4:c\n5:d\n");
+    }
+
+    #[test]
+    fn prints_each_line_of_a_multiline_match() {
+        let cfg = Config {
+            line_number: true,
+            byte_offset: true,
+            before_context: 1,
+            color: ColorChoice::Always,
+            ..Config::default()
+        };
+        let events = [
+            Event::Context(line(5, "")),
+            Event::Match(Match {
+                spans: vec![(13, 31)],
+                ..line(6, "How dreary to be somebody!\r\nHow public, like a frog")
+            }),
+        ];
+        let printed = print(&cfg, false, &events).replace("\x1b[0m", ">");
+        let printed = printed.replace("\x1b[1;31m", "<").replace('\x1b', "");
+        assert_eq!(
+            printed,
+            "[32m5>[36m->[33m137>[36m->\n\
+             [32m6>[36m:>[33m137>[36m:>How dreary to< be somebody!>\n\
+             [32m7>[36m:>[33m165>[36m:><How> public, like a frog\n"
+        );
+    }
+
+    #[test]
+    fn summarizes_files_in_count_and_file_list_modes() {
+        let events = [frog(), Event::Context(line(8, "To tell")), frog()];
+        let cfg = |output| Config {
+            output,
+            ..Config::default()
+        };
+
+        assert_eq!(print(&cfg(OutputMode::Count), false, &events), "2\n");
+        assert_eq!(print(&cfg(OutputMode::Count), true, &[]), "poem.txt:0\n");
+        assert_eq!(
+            print(&cfg(OutputMode::FilesWithMatches), false, &events),
+            "poem.txt\n"
+        );
+        assert_eq!(print(&cfg(OutputMode::FilesWithMatches), false, &[]), "");
+        assert_eq!(print(&cfg(OutputMode::FilesWithoutMatch), false, &events), "");
+        assert_eq!(
+            print(&cfg(OutputMode::FilesWithoutMatch), false, &[]),
+            "poem.txt\n"
+        );
+    }
+
+    #[test]
+    fn summarizes_binary_files_instead_of_printing_their_lines() {
+        let cfg = Config {
+            before_context: 1,
+            ..Config::default()
+        };
+        let events = [Event::Binary, Event::Context(line(6, "\0")), frog(), frog()];
+        assert_eq!(print(&cfg, true, &events), "Binary file poem.txt matches\n");
+        assert_eq!(print(&cfg, true, &events[..2]), "");
+
+        let cfg = Config {
+            output: OutputMode::Count,
+            ..Config::default()
+        };
+        assert_eq!(print(&cfg, false, &events), "2\n");
+    }
+
+    #[test]
+    fn highlights_matches_and_prefixes_when_colored() {
+        let cfg = Config {
+            line_number: true,
+            color: ColorChoice::Always,
+            ..Config::default()
+        };
+        assert_eq!(
+            print(&cfg, true, &[Event::Context(line(6, "a frog")), frog()]),
+            "\x1b[35mpoem.txt\x1b[0m\x1b[36m-\x1b[0m\x1b[32m6\x1b[0m\x1b[36m-\x1b[0ma frog\n\
+             \x1b[35mpoem.txt\x1b[0m\x1b[36m:\x1b[0m\x1b[32m7\x1b[0m\x1b[36m:\x1b[0m\
+             How public, like a \x1b[1;31mfrog\x1b[0m\n"
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use encoding_rs::{Encoding, UTF_8};
+use matcher::Matcher;
+use search::{trim_terminator, Event, Match, Sink};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::process;
+use {BinaryMode, Config};
+
+// How much of a file is checked for NUL bytes: `BufReader`'s buffer size,
+// so that a file looks binary here exactly when it does to the searcher.
+const BLOCK: usize = 8 * 1024;
+
+/// Prints what replacing every match would do to each file as a unified
+/// diff, leaving the files themselves alone.
+///
+/// Hunks only hold the changed lines unless context was asked for, with
+/// `-C` and friends.
+pub struct DiffPrinter<'m, W> {
+    matcher: &'m Matcher,
+    replacement: &'m str,
+    out: W,
+    path: String,
+    // whether this file's `---`/`+++` header has been printed yet
+    header: bool,
+    // the hunk being put together: each line with its ` `, `-` or `+`
+    hunk: Vec<(u8, Vec<u8>)>,
+    old_start: usize,
+    // the line number that carries on the current hunk
+    next_line: usize,
+    // changed lines not yet in the hunk, kept apart so that a run of them
+    // reads as all the old lines followed by all the new ones
+    removed: Vec<Vec<u8>>,
+    added: Vec<Vec<u8>>,
+    // how many more lines the new file has than the old one so far
+    offset: isize,
+}
+
+impl<'m, W: Write> DiffPrinter<'m, W> {
+    pub fn new(matcher: &'m Matcher, replacement: &'m str, out: W) -> Self {
+        DiffPrinter {
+            matcher,
+            replacement,
+            out,
+            path: String::new(),
+            header: false,
+            hunk: Vec::new(),
+            old_start: 0,
+            next_line: 0,
+            removed: Vec::new(),
+            added: Vec::new(),
+            offset: 0,
+        }
+    }
+
+    fn line(&mut self, m: &Match, selected: bool) -> io::Result<()> {
+        if m.line_number != self.next_line {
+            self.finish_hunk()?;
+            self.old_start = m.line_number;
+        }
+        self.next_line = m.end_line_number() + 1;
+        let replaced = match selected {
+            true => self.matcher.replace_all(&m.line, self.replacement),
+            false => m.line.clone(),
+        };
+        // a multiline match covers several lines, and the replacement may
+        // have split or joined them
+        let lines = |text: &[u8]| -> Vec<Vec<u8>> {
+            text.split(|&b| b == b'\n')
+                .map(|line| line.strip_suffix(b"\r").unwrap_or(line).to_vec())
+                .collect()
+        };
+        if replaced == m.line {
+            self.finish_run();
+            let context = lines(&replaced).into_iter().map(|line| (b' ', line));
+            self.hunk.extend(context);
+        } else {
+            self.removed.extend(lines(&m.line));
+            self.added.extend(lines(&replaced));
+        }
+        Ok(())
+    }
+
+    fn finish_run(&mut self) {
+        let removed = self.removed.drain(..).map(|line| (b'-', line));
+        self.hunk.extend(removed);
+        let added = self.added.drain(..).map(|line| (b'+', line));
+        self.hunk.extend(added);
+    }
+
+    fn finish_hunk(&mut self) -> io::Result<()> {
+        self.finish_run();
+        let hunk: Vec<_> = self.hunk.drain(..).collect();
+        if hunk.iter().all(|&(kind, _)| kind == b' ') {
+            return Ok(());
+        }
+        if !self.header {
+            writeln!(self.out, "--- {}", self.path)?;
+            writeln!(self.out, "+++ {}", self.path)?;
+            self.header = true;
+        }
+        let old_len = hunk.iter().filter(|&&(kind, _)| kind != b'+').count();
+        let new_len = hunk.iter().filter(|&&(kind, _)| kind != b'-').count();
+        let new_start = self.old_start as isize + self.offset;
+        writeln!(
+            self.out,
+            "@@ -{},{} +{},{} @@",
+            self.old_start, old_len, new_start, new_len
+        )?;
+        for (kind, line) in hunk {
+            self.out.write_all(&[kind])?;
+            self.out.write_all(&line)?;
+            self.out.write_all(b"\n")?;
+        }
+        self.offset += new_len as isize - old_len as isize;
+        Ok(())
+    }
+}
+
+impl<'m, W: Write> Sink for DiffPrinter<'m, W> {
+    fn begin(&mut self, path: &Path, _with_filename: bool) -> io::Result<()> {
+        self.path = path.display().to_string();
+        self.header = false;
+        self.next_line = 0;
+        self.offset = 0;
+        Ok(())
+    }
+
+    fn event(&mut self, event: Event) -> io::Result<()> {
+        match event {
+            Event::Match(ref m) => self.line(m, true),
+            Event::Context(ref m) => self.line(m, false),
+            // the searcher refuses binary files before this when replacing
+            Event::Binary => Ok(()),
+        }
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        self.finish_hunk()
+    }
+}
+
+/// Replaces every match in the file at `path`, returning whether anything
+/// changed.
+///
+/// The new contents are written to a temporary file beside the old one,
+/// given the same permissions, and renamed over it, so the file is never
+/// seen half-written. Binary files are refused, as are files that would
+/// have to be transcoded, since they couldn't be written back as they were.
+pub fn rewrite(
+    matcher: &Matcher,
+    replacement: &str,
+    cfg: &Config,
+    path: &Path,
+) -> io::Result<bool> {
+    // rewrite what a symlink points to rather than replacing the link
+    let path = fs::canonicalize(path)?;
+    let original = fs::read(&path)?;
+    let encoding = match Encoding::for_bom(&original) {
+        Some((encoding, _)) => Some(encoding),
+        None => cfg.encoding,
+    };
+    if let Some(encoding) = encoding.filter(|&e| e != UTF_8) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("can't rewrite {} files in place", encoding.name()),
+        ));
+    }
+    if original[..original.len().min(BLOCK)].contains(&0) {
+        if cfg.binary == BinaryMode::Skip {
+            return Ok(false);
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "binary file, not replacing",
+        ));
+    }
+
+    let replaced = if cfg.multiline {
+        matcher.replace_all(&original, replacement)
+    } else {
+        let mut replaced = Vec::with_capacity(original.len());
+        for line in original.split_inclusive(|&b| b == b'\n') {
+            let text = trim_terminator(line);
+            replaced.extend(matcher.replace_all(text, replacement));
+            replaced.extend_from_slice(&line[text.len()..]);
+        }
+        replaced
+    };
+    if replaced == original {
+        return Ok(false);
+    }
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.minigrep_v2-{}", name, process::id()));
+    let permissions = fs::metadata(&path)?.permissions();
+    let written = write_new(&temp, &replaced, permissions).and_then(|()| fs::rename(&temp, &path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written.map(|()| true)
+}
+
+fn write_new(path: &Path, contents: &[u8], permissions: fs::Permissions) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    file.write_all(contents)?;
+    file.set_permissions(permissions)?;
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use matcher::MatcherKind;
+    use search::Searcher;
+
+    fn preview(query: &str, replacement: &str, context: usize, contents: &str) -> String {
+        let cfg = Config {
+            patterns: vec![query.to_string()],
+            matcher: MatcherKind::Regex,
+            before_context: context,
+            after_context: context,
+            replace: Some(replacement.to_string()),
+            ..Config::default()
+        };
+        let matcher = Matcher::from_config(&cfg).unwrap();
+        let mut out = Vec::new();
+        {
+            let mut printer = DiffPrinter::new(&matcher, replacement, &mut out);
+            printer.begin(Path::new("poem.txt"), false).unwrap();
+            Searcher::new(&matcher, &cfg)
+                .search("poem.txt", contents.as_bytes(), |event| printer.event(event))
+                .unwrap();
+            printer.end().unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    const POEM: &str = "\
+I'm nobody! Who are you?
+Are you nobody, too?
+Then there's a pair of us - don't tell!
+They'd banish us, you know.
+
+How dreary to be somebody!
+How public, like a frog
+To tell your name the livelong day
+To an admiring bog!
+";
+
+    #[test]
+    fn prints_a_hunk_per_run_of_changed_lines() {
+        assert_eq!(
+            preview(r"(\w+)body", "${1}one", 0, POEM),
+            "\
+--- poem.txt
++++ poem.txt
+@@ -1,2 +1,2 @@
+-I'm nobody! Who are you?
+-Are you nobody, too?
++I'm noone! Who are you?
++Are you noone, too?
+@@ -6,1 +6,1 @@
+-How dreary to be somebody!
++How dreary to be someone!
+"
+        );
+    }
+
+    #[test]
+    fn shows_context_and_keeps_line_numbers_straight() {
+        assert_eq!(
+            preview("frog", "toad\nor newt", 1, POEM),
+            "\
+--- poem.txt
++++ poem.txt
+@@ -6,3 +6,4 @@
+ How dreary to be somebody!
+-How public, like a frog
++How public, like a toad
++or newt
+ To tell your name the livelong day
+"
+        );
+        let diff = preview("(frog|bog)", "$1\n", 0, POEM);
+        assert!(diff.contains("@@ -7,1 +7,2 @@\n"));
+        assert!(diff.ends_with(
+            "@@ -9,1 +10,2 @@\n-To an admiring bog!\n+To an admiring bog\n+!\n"
+        ));
+    }
+
+    #[test]
+    fn prints_nothing_when_nothing_changes() {
+        assert_eq!(preview("toad", "frog", 2, POEM), "");
+        assert_eq!(preview("frog", "frog", 2, POEM), "");
+    }
+}
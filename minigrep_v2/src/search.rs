@@ -0,0 +1,678 @@
+use decode::Decoder;
+use encoding_rs::Encoding;
+use matcher::Matcher;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use {BinaryMode, Config};
+
+/// A line of input that matched the query, and where it was found.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Match {
+    /// The file the line was read from
+    pub path: String,
+    /// 1-based number of the line within its file
+    pub line_number: usize,
+    /// Offset of the line's first byte from the start of its file
+    pub byte_offset: usize,
+    /// The line itself, without its terminator. Kept as raw bytes since
+    /// input isn't required to be valid UTF-8. In multiline mode, this is
+    /// every line the match covers, each but the last still terminated.
+    pub line: Vec<u8>,
+    /// `(start, end)` byte ranges of every occurrence of the query in `line`
+    pub spans: Vec<(usize, usize)>,
+    /// The edit distance of each of `spans` from the query, when matching
+    /// fuzzily (and empty otherwise)
+    pub distances: Vec<usize>,
+}
+
+impl Match {
+    /// The line as text, with any invalid UTF-8 replaced by `U+FFFD`.
+    pub fn text(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.line)
+    }
+
+    /// The number of the last line covered, which is only ever after
+    /// `line_number` in multiline mode.
+    pub fn end_line_number(&self) -> usize {
+        self.line_number + self.line.iter().filter(|&&b| b == b'\n').count()
+    }
+}
+
+/// A line reported by a search: either one that was selected (it matched,
+/// or didn't when inverted), or one printed around it for context.
+///
+/// `Binary` comes before any lines of a file that looks binary.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Event {
+    Match(Match),
+    Context(Match),
+    Binary,
+}
+
+/// Receives what a search turns up, one file at a time.
+pub trait Sink {
+    fn begin(&mut self, _path: &Path, _with_filename: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn event(&mut self, event: Event) -> io::Result<()>;
+
+    fn end(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Collects just the selected lines, dropping any context.
+impl Sink for Vec<Match> {
+    fn event(&mut self, event: Event) -> io::Result<()> {
+        if let Event::Match(m) = event {
+            self.push(m);
+        }
+        Ok(())
+    }
+}
+
+/// How far a search of a file has got, for `Searcher::resume` to carry on
+/// from when more of the file turns up.
+pub struct Progress {
+    decoder: Decoder,
+    binary: bool,
+    // lines we may yet need as leading context for a match
+    before: VecDeque<Match>,
+    // how many more lines of trailing context the last match is owed
+    after: usize,
+    // the number of lines searched, and the bytes of the original they took
+    line_number: usize,
+    byte_offset: usize,
+}
+
+impl Progress {
+    /// Carries on from `byte_offset`, the start of line `line_number`,
+    /// rather than the top of the file, for a file that was partly there
+    /// before anyone started searching it.
+    pub fn at(self, line_number: usize, byte_offset: usize) -> Progress {
+        Progress {
+            line_number: line_number - 1,
+            byte_offset,
+            ..self
+        }
+    }
+}
+
+/// Runs a `Matcher` over the lines of a file, reporting the selected lines
+/// and up to `before`/`after` lines of context around each of them.
+///
+/// Input is streamed a line at a time, so memory use depends on the length
+/// of the longest line (and the amount of context asked for) rather than on
+/// the size of the file. Files in other encodings are transcoded to UTF-8 a
+/// line at a time too; byte offsets still count bytes of the original.
+///
+/// The exception is multiline mode, where matches may span lines and each
+/// file is read into memory whole.
+pub struct Searcher<'m> {
+    matcher: &'m Matcher,
+    invert: bool,
+    before: usize,
+    after: usize,
+    binary: BinaryMode,
+    encoding: Option<&'static Encoding>,
+    // binary files are an error when their matches are to be replaced
+    replacing: bool,
+    multiline: bool,
+}
+
+impl<'m> Searcher<'m> {
+    pub fn new(matcher: &'m Matcher, cfg: &Config) -> Self {
+        Searcher {
+            matcher,
+            invert: cfg.invert_match,
+            before: cfg.before_context,
+            after: cfg.after_context,
+            binary: cfg.binary,
+            encoding: cfg.encoding,
+            replacing: cfg.replace.is_some(),
+            multiline: cfg.multiline,
+        }
+    }
+
+    /// Hands each line worth reporting to `sink` as soon as it's found.
+    pub fn search<R, F>(&self, path: &str, mut reader: R, mut sink: F) -> io::Result<()>
+    where
+        R: BufRead,
+        F: FnMut(Event) -> io::Result<()>,
+    {
+        let mut progress = self.start(&mut reader)?;
+        if self.multiline {
+            if !self.announce_binary(&progress, &mut sink)? {
+                return Ok(());
+            }
+            let bom = progress.byte_offset;
+            return self.search_multiline(path, reader, progress.decoder, bom, sink);
+        }
+        self.resume(path, &mut progress, reader, sink)
+    }
+
+    /// Gets ready to search a file a piece at a time, working out its
+    /// encoding and whether it's binary from `head`, its beginning. Any
+    /// byte order mark is consumed.
+    ///
+    /// Not for multiline mode, which needs the whole file at once.
+    pub fn start<R: BufRead>(&self, head: &mut R) -> io::Result<Progress> {
+        let (decoder, bom) = Decoder::sniff(head, self.encoding)?;
+        // like grep, only the first block is checked, so binary data further
+        // in still gets treated as text
+        let binary = !decoder.is_utf16() && head.fill_buf()?.contains(&0);
+        Ok(Progress {
+            decoder,
+            binary,
+            before: VecDeque::with_capacity(self.before),
+            after: 0,
+            line_number: 0,
+            byte_offset: bom,
+        })
+    }
+
+    /// Searches `reader` as the next piece of the file that `progress` was
+    /// started on, carrying on where the last piece left off: with the same
+    /// encoding, its lines numbered after the last piece's, and context
+    /// running on from one piece to the next. A binary file is announced
+    /// again at the start of every piece.
+    pub fn resume<R, F>(
+        &self,
+        path: &str,
+        progress: &mut Progress,
+        mut reader: R,
+        mut sink: F,
+    ) -> io::Result<()>
+    where
+        R: BufRead,
+        F: FnMut(Event) -> io::Result<()>,
+    {
+        if !self.announce_binary(progress, &mut sink)? {
+            return Ok(());
+        }
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            let read = progress.decoder.read_line(&mut reader, &mut buf)?;
+            if read == 0 {
+                return Ok(());
+            }
+            progress.line_number += 1;
+            let line = trim_terminator(&buf);
+            let (spans, distances) = self.matcher.find_all_with_distances(line);
+            let selected = spans.is_empty() == self.invert;
+
+            // only copy the line out of the buffer if it's going somewhere
+            if selected || progress.after > 0 || self.before > 0 {
+                let line = Match {
+                    path: path.to_string(),
+                    line_number: progress.line_number,
+                    byte_offset: progress.byte_offset,
+                    line: line.to_vec(),
+                    spans,
+                    distances,
+                };
+                if selected {
+                    for context in progress.before.drain(..) {
+                        sink(Event::Context(context))?;
+                    }
+                    sink(Event::Match(line))?;
+                    progress.after = self.after;
+                } else if progress.after > 0 {
+                    sink(Event::Context(line))?;
+                    progress.after -= 1;
+                } else {
+                    if progress.before.len() == self.before {
+                        progress.before.pop_front();
+                    }
+                    progress.before.push_back(line);
+                }
+            }
+            progress.byte_offset += read;
+        }
+    }
+
+    // Tells `sink` about a binary file before any of its lines, returning
+    // whether to search it at all.
+    fn announce_binary<F>(&self, progress: &Progress, sink: &mut F) -> io::Result<bool>
+    where
+        F: FnMut(Event) -> io::Result<()>,
+    {
+        if !progress.binary {
+            return Ok(true);
+        }
+        match self.binary {
+            BinaryMode::Skip => Ok(false),
+            // replacing bytes in them would only corrupt them, so not
+            // even `--text` talks us into it
+            _ if self.replacing => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "binary file, not replacing",
+            )),
+            BinaryMode::Summarize => sink(Event::Binary).map(|_| true),
+            BinaryMode::Text => Ok(true),
+        }
+    }
+
+    // Matches against the whole of the input at once, reporting each group
+    // of lines that matches cover as a single `Match`.
+    fn search_multiline<R, F>(
+        &self,
+        path: &str,
+        mut reader: R,
+        mut decoder: Decoder,
+        bom: usize,
+        mut sink: F,
+    ) -> io::Result<()>
+    where
+        R: BufRead,
+        F: FnMut(Event) -> io::Result<()>,
+    {
+        let mut text = Vec::new();
+        // where each line starts in `text` and in the original, with one
+        // more entry for where the last one ends
+        let mut starts = vec![0];
+        let mut offsets = vec![bom];
+        loop {
+            let read = decoder.read_line(&mut reader, &mut text)?;
+            if read == 0 {
+                break;
+            }
+            starts.push(text.len());
+            offsets.push(offsets[offsets.len() - 1] + read);
+        }
+        let lines = starts.len() - 1;
+        let line_of = |at: usize| starts.partition_point(|&start| start <= at) - 1;
+
+        let mut groups: Vec<Group> = Vec::new();
+        let (spans, distances) = self.matcher.find_all_with_distances(&text);
+        for (i, &(start, end)) in spans.iter().enumerate() {
+            if start >= text.len() {
+                // an empty match after the last line isn't on any line
+                break;
+            }
+            let first = line_of(start);
+            let last = if end > start { line_of(end - 1) } else { first };
+            let distance = distances.get(i).cloned();
+            match groups.last_mut() {
+                Some(group) if first <= group.last => {
+                    group.last = group.last.max(last);
+                    group.spans.push((start, end));
+                    group.distances.extend(distance);
+                }
+                _ => groups.push(Group {
+                    first,
+                    last,
+                    spans: vec![(start, end)],
+                    distances: distance.into_iter().collect(),
+                }),
+            }
+        }
+        if self.invert {
+            let mut covered = vec![false; lines];
+            for group in &groups {
+                for covered in &mut covered[group.first..=group.last] {
+                    *covered = true;
+                }
+            }
+            groups = (0..lines)
+                .filter(|&i| !covered[i])
+                .map(Group::line)
+                .collect();
+        }
+
+        let report = |group: Group| {
+            let first = group.first;
+            let line = trim_terminator(&text[starts[first]..starts[group.last + 1]]);
+            let spans = group
+                .spans
+                .into_iter()
+                .map(|(start, end)| {
+                    let end = (end - starts[first]).min(line.len());
+                    ((start - starts[first]).min(end), end)
+                }).collect();
+            Match {
+                path: path.to_string(),
+                line_number: first + 1,
+                byte_offset: offsets[first],
+                line: line.to_vec(),
+                spans,
+                distances: group.distances,
+            }
+        };
+        let context = |i| Event::Context(report(Group::line(i)));
+        // the first line not yet reported, and the end of the trailing
+        // context owed to the last group
+        let mut next = 0;
+        let mut after = 0;
+        for group in groups {
+            let (first, last) = (group.first, group.last);
+            for i in next..after.min(first) {
+                sink(context(i))?;
+            }
+            for i in next.max(after).max(first.saturating_sub(self.before))..first {
+                sink(context(i))?;
+            }
+            sink(Event::Match(report(group)))?;
+            next = last + 1;
+            after = next + self.after;
+        }
+        for i in next..after.min(lines) {
+            sink(context(i))?;
+        }
+        Ok(())
+    }
+}
+
+// Matches in multiline mode that share lines, and the lines they cover
+// (counted from 0).
+struct Group {
+    first: usize,
+    last: usize,
+    spans: Vec<(usize, usize)>,
+    distances: Vec<usize>,
+}
+
+impl Group {
+    // a line on its own, without any matches
+    fn line(i: usize) -> Group {
+        Group {
+            first: i,
+            last: i,
+            spans: Vec::new(),
+            distances: Vec::new(),
+        }
+    }
+}
+
+/// Drops `\n` and `\r\n` terminators the same way `str::lines` does.
+pub fn trim_terminator(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use matcher::MatcherKind;
+    use std::cmp;
+
+    fn search(query: &str, cfg: &Config, contents: &str) -> Vec<String> {
+        let matcher = Matcher::new(query, MatcherKind::Literal, true).unwrap();
+        events(&matcher, cfg, contents)
+    }
+
+    fn events(matcher: &Matcher, cfg: &Config, contents: &str) -> Vec<String> {
+        let mut events = Vec::new();
+        Searcher::new(matcher, cfg)
+            .search("f", contents.as_bytes(), |event| {
+                events.push(match event {
+                    Event::Match(m) => format!("{}:{}", m.line_number, m.text()),
+                    Event::Context(m) => format!("{}-{}", m.line_number, m.text()),
+                    Event::Binary => String::from("binary"),
+                });
+                Ok(())
+            }).unwrap();
+        events
+    }
+
+    #[test]
+    fn reports_where_each_occurrence_was_found() {
+        let matcher = Matcher::new("o", MatcherKind::Literal, true).unwrap();
+        let mut matches = Vec::new();
+        Searcher::new(&matcher, &Config::default())
+            .search(
+                "poem.txt",
+                &b"Rust:\r\nsafe, fast, productive.\n\nPick two."[..],
+                |event| matches.event(event),
+            ).unwrap();
+        assert_eq!(
+            matches,
+            vec![
+                Match {
+                    path: String::from("poem.txt"),
+                    line_number: 2,
+                    byte_offset: 7,
+                    line: b"safe, fast, productive.".to_vec(),
+                    spans: vec![(14, 15)],
+                    distances: vec![],
+                },
+                Match {
+                    path: String::from("poem.txt"),
+                    line_number: 4,
+                    byte_offset: 32,
+                    line: b"Pick two.".to_vec(),
+                    spans: vec![(7, 8)],
+                    distances: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_context_around_matches() {
+        let cfg = Config {
+            before_context: 2,
+            after_context: 1,
+            ..Config::default()
+        };
+        assert_eq!(
+            search("x", &cfg, "a\nb\nc\nx\nd\ne\nf\ng\nx\nx\nh\n"),
+            vec!["2-b", "3-c", "4:x", "5-d", "7-f", "8-g", "9:x", "10:x", "11-h"]
+        );
+    }
+
+    #[test]
+    fn selects_non_matching_lines_when_inverted() {
+        let cfg = Config {
+            invert_match: true,
+            after_context: 1,
+            ..Config::default()
+        };
+        assert_eq!(search("x", &cfg, "a\nx\nx\nb\n"), vec!["1:a", "2-x", "4:b"]);
+    }
+
+    #[test]
+    fn searches_lines_that_are_not_valid_utf8() {
+        let matcher = Matcher::new("frog", MatcherKind::Literal, true).unwrap();
+        let mut matches = Vec::new();
+        Searcher::new(&matcher, &Config::default())
+            .search("f", &b"toad\n\xff\xfe frog\n"[..], |event| matches.event(event))
+            .unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].line, b"\xff\xfe frog".to_vec());
+        assert_eq!(matches[0].text(), "\u{fffd}\u{fffd} frog");
+    }
+
+    #[test]
+    fn flags_files_with_a_nul_in_their_first_block_as_binary() {
+        let contents = "frog\0\x01\nfrog\n";
+        assert_eq!(
+            search("frog", &Config::default(), contents),
+            vec!["binary", "1:frog\u{0}\u{1}", "2:frog"]
+        );
+        let cfg = |binary| Config {
+            binary,
+            ..Config::default()
+        };
+        assert_eq!(
+            search("frog", &cfg(BinaryMode::Text), contents),
+            vec!["1:frog\u{0}\u{1}", "2:frog"]
+        );
+        assert!(search("frog", &cfg(BinaryMode::Skip), contents).is_empty());
+        assert_eq!(search("frog", &cfg(BinaryMode::Skip), "frog\n"), vec!["1:frog"]);
+    }
+
+    fn search_in_pieces(query: &str, cfg: &Config, pieces: &[&str]) -> Vec<String> {
+        let matcher = Matcher::new(query, MatcherKind::Literal, true).unwrap();
+        let searcher = Searcher::new(&matcher, cfg);
+        let mut progress = searcher.start(&mut pieces[0].as_bytes()).unwrap();
+        let mut events = Vec::new();
+        let at = |m: &Match| format!("{}@{}", m.line_number, m.byte_offset);
+        for piece in pieces {
+            searcher
+                .resume("f", &mut progress, piece.as_bytes(), |event| {
+                    events.push(match event {
+                        Event::Match(m) => format!("{}:{}", at(&m), m.text()),
+                        Event::Context(m) => format!("{}-{}", at(&m), m.text()),
+                        Event::Binary => String::from("binary"),
+                    });
+                    Ok(())
+                }).unwrap();
+        }
+        events
+    }
+
+    #[test]
+    fn resumes_where_the_last_piece_left_off() {
+        let cfg = Config {
+            before_context: 1,
+            after_context: 1,
+            ..Config::default()
+        };
+        // context runs across pieces, and only the first is checked for NULs
+        assert_eq!(
+            search_in_pieces("x", &cfg, &["a\nb\n", "x\nc\n\0d\n", "e\nx\n"]),
+            vec!["2@2-b", "3@4:x", "4@6-c", "6@11-e", "7@13:x"]
+        );
+        assert_eq!(
+            search_in_pieces("x", &Config::default(), &["\0\n", "x\n"]),
+            vec!["binary", "binary", "2@2:x"]
+        );
+    }
+
+    // produces `lines` numbered lines on demand, so the whole "file" never
+    // exists in memory at once
+    struct Generated {
+        lines: usize,
+        next: usize,
+        pending: Vec<u8>,
+    }
+
+    impl Read for Generated {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.pending.is_empty() && self.next < self.lines {
+                self.next += 1;
+                self.pending = format!("line {:08} of filler text\n", self.next).into_bytes();
+            }
+            let n = cmp::min(buf.len(), self.pending.len());
+            buf[..n].copy_from_slice(&self.pending[..n]);
+            self.pending.drain(..n);
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn streams_input_much_larger_than_its_read_buffer() {
+        let matcher = Matcher::new("7777", MatcherKind::Literal, true).unwrap();
+        let input = Generated {
+            lines: 100_000,
+            next: 0,
+            pending: Vec::new(),
+        };
+        let mut matches = Vec::new();
+        Searcher::new(&matcher, &Config::default())
+            .search("f", io::BufReader::with_capacity(64, input), |event| {
+                matches.event(event)
+            }).unwrap();
+
+        let found: Vec<_> = matches.iter().map(|m| m.line_number).collect();
+        let expected: Vec<_> = (1..100_001)
+            .filter(|n| format!("{:08}", n).contains("7777"))
+            .collect();
+        assert_eq!(found, expected);
+        let line_len = "line 00000000 of filler text\n".len();
+        assert_eq!(matches.last().unwrap().byte_offset, (97777 - 1) * line_len);
+    }
+
+    fn search_multiline(pattern: &str, cfg: Config, contents: &str) -> Vec<String> {
+        let cfg = Config {
+            patterns: vec![pattern.to_string()],
+            matcher: MatcherKind::Regex,
+            multiline: true,
+            ..cfg
+        };
+        events(&Matcher::from_config(&cfg).unwrap(), &cfg, contents)
+    }
+
+    const SOURCE: &str = "\
+use std::io;
+
+fn search(
+    query: &str,
+) -> bool {
+    query.is_empty()
+}
+";
+
+    #[test]
+    fn matches_across_lines_in_multiline_mode() {
+        assert_eq!(
+            search_multiline(r"fn \w+\(\n\s+query", Config::default(), SOURCE),
+            vec!["3:fn search(\n    query: &str,"]
+        );
+        // matches sharing a line are reported together
+        assert_eq!(
+            search_multiline(r"fn search|\(\n\s+query|^\}", Config::default(), SOURCE),
+            vec!["3:fn search(\n    query: &str,", "7:}"]
+        );
+        // a match that ends with a line's terminator doesn't take in the
+        // next line
+        assert_eq!(
+            search_multiline(r"io;\n", Config::default(), SOURCE),
+            vec!["1:use std::io;"]
+        );
+    }
+
+    #[test]
+    fn reports_where_multiline_matches_start_and_end() {
+        let cfg = Config {
+            patterns: vec![String::from(r"b\r?\nc")],
+            matcher: MatcherKind::Regex,
+            multiline: true,
+            ..Config::default()
+        };
+        let matcher = Matcher::from_config(&cfg).unwrap();
+        let mut matches = Vec::new();
+        Searcher::new(&matcher, &cfg)
+            .search("f", &b"a\r\nb\r\nc\r\nd\r\n"[..], |event| matches.event(event))
+            .unwrap();
+        assert_eq!(
+            matches,
+            vec![Match {
+                path: String::from("f"),
+                line_number: 2,
+                byte_offset: 3,
+                line: b"b\r\nc".to_vec(),
+                spans: vec![(0, 4)],
+                distances: vec![],
+            }]
+        );
+        assert_eq!(matches[0].end_line_number(), 3);
+    }
+
+    #[test]
+    fn gives_multiline_matches_context_and_inverts_them() {
+        let cfg = Config {
+            before_context: 1,
+            after_context: 1,
+            ..Config::default()
+        };
+        assert_eq!(
+            search_multiline(r"\(\n.*,", cfg, SOURCE),
+            vec!["2-", "3:fn search(\n    query: &str,", "5-) -> bool {"]
+        );
+        let cfg = Config {
+            invert_match: true,
+            ..Config::default()
+        };
+        assert_eq!(
+            search_multiline(r"\{\n.*\n\}", cfg, SOURCE),
+            vec!["1:use std::io;", "2:", "3:fn search(", "4:    query: &str,"]
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+/// Follows a file as it grows, like `tail -F`, handing back the lines added
+/// to it since the last look.
+///
+/// Following starts after the last finished line of the file, or at its
+/// beginning if it doesn't exist yet. A file that shrinks is taken to have been truncated
+/// and is read again from the top. One that's replaced by another (on Unix,
+/// where files can be told apart) is taken to have been rotated: whatever
+/// was left to read of the old one comes first, then the new one from the
+/// top. Until a line is finished with a newline it's held back, unless its
+/// file has been rotated away and won't be getting any more.
+pub struct Tail {
+    path: PathBuf,
+    file: Option<Open>,
+}
+
+// The file currently being followed.
+struct Open {
+    file: File,
+    id: Option<Id>,
+    // how much of the file has been read
+    offset: u64,
+    // the number of the line after the last one handed back
+    line_number: usize,
+    // the start of a line that hasn't been finished yet
+    partial: Vec<u8>,
+}
+
+/// Lines read by `Tail::poll`, each with its terminator.
+#[derive(Debug, PartialEq)]
+pub struct Lines {
+    /// The number of the first line within its file
+    pub line_number: usize,
+    /// The offset of the first line from the start of its file
+    pub byte_offset: u64,
+    pub text: Vec<u8>,
+}
+
+impl Tail {
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Tail> {
+        let path = path.as_ref().to_path_buf();
+        let file = match Open::new(&path) {
+            Ok(mut open) => {
+                // only what's added from now on is of interest, but line
+                // numbers should still count what came before it, and a line
+                // still being written is held back until it's finished
+                let mut reader = BufReader::new(&open.file);
+                loop {
+                    let block = reader.fill_buf()?;
+                    if block.is_empty() {
+                        break;
+                    }
+                    open.line_number += block.iter().filter(|&&b| b == b'\n').count();
+                    match block.iter().rposition(|&b| b == b'\n') {
+                        Some(end) => {
+                            open.partial.clear();
+                            open.partial.extend_from_slice(&block[end + 1..]);
+                        }
+                        None => open.partial.extend_from_slice(block),
+                    }
+                    open.offset += block.len() as u64;
+                    let len = block.len();
+                    reader.consume(len);
+                }
+                Some(open)
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        Ok(Tail { path, file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The line number and byte offset that the next lines read from the
+    /// file being followed will start at, if there is one yet.
+    pub fn position(&self) -> Option<(usize, u64)> {
+        self.file
+            .as_ref()
+            .map(|open| (open.line_number, open.offset - open.partial.len() as u64))
+    }
+
+    /// Whatever lines have been added since the last poll: usually one run
+    /// of them, but two if the file was rotated in between.
+    pub fn poll(&mut self) -> io::Result<Vec<Lines>> {
+        let mut lines = Vec::new();
+        let metadata = match fs::metadata(&self.path) {
+            Ok(metadata) => Some(metadata),
+            // moved away, and its replacement not there yet
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        let replaced = match (&self.file, &metadata) {
+            (Some(open), Some(metadata)) => open.id.is_some() && open.id != id(metadata),
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        if replaced {
+            if let Some(mut old) = self.file.take() {
+                lines.extend(old.read(true)?);
+            }
+        }
+        if metadata.is_none() {
+            return Ok(lines);
+        }
+        if self.file.is_none() {
+            self.file = Some(Open::new(&self.path)?);
+        }
+        if let Some(ref mut open) = self.file {
+            if metadata.is_some_and(|m| m.len() < open.offset) {
+                *open = Open::new(&self.path)?;
+            }
+            lines.extend(open.read(false)?);
+        }
+        Ok(lines)
+    }
+}
+
+impl Open {
+    fn new(path: &Path) -> io::Result<Open> {
+        let file = File::open(path)?;
+        let id = id(&file.metadata()?);
+        Ok(Open {
+            file,
+            id,
+            offset: 0,
+            line_number: 1,
+            partial: Vec::new(),
+        })
+    }
+
+    // Reads to the end of the file, returning every line finished along
+    // the way, or the unfinished one too if this is the `last` read.
+    fn read(&mut self, last: bool) -> io::Result<Option<Lines>> {
+        let start = self.offset - self.partial.len() as u64;
+        let mut text = mem::take(&mut self.partial);
+        let read = self.file.read_to_end(&mut text)?;
+        self.offset += read as u64;
+        if !last {
+            let end = text.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+            self.partial = text.split_off(end);
+        }
+        if text.is_empty() {
+            return Ok(None);
+        }
+        let lines = Lines {
+            line_number: self.line_number,
+            byte_offset: start,
+            text,
+        };
+        self.line_number += lines.text.iter().filter(|&&b| b == b'\n').count();
+        Ok(Some(lines))
+    }
+}
+
+// What tells one file from another: its device and inode.
+#[cfg(unix)]
+type Id = (u64, u64);
+
+#[cfg(unix)]
+fn id(metadata: &fs::Metadata) -> Option<Id> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+type Id = ();
+
+#[cfg(not(unix))]
+fn id(_metadata: &fs::Metadata) -> Option<Id> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common;
+    use std::fs::OpenOptions;
+
+    fn scratch_file(name: &str, contents: &str) -> PathBuf {
+        common::fixture_dir(&format!("tail-{}", name), &[("app.log", contents)]).join("app.log")
+    }
+
+    fn append(path: &Path, text: &str) {
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+
+    fn poll(tail: &mut Tail) -> Vec<(usize, u64, String)> {
+        tail.poll()
+            .unwrap()
+            .into_iter()
+            .map(|lines| {
+                let text = String::from_utf8(lines.text).unwrap();
+                (lines.line_number, lines.byte_offset, text)
+            }).collect()
+    }
+
+    #[test]
+    fn reads_only_finished_lines_added_since_the_last_poll() {
+        let path = scratch_file("append", "old\nlines\n");
+        let mut tail = Tail::new(&path).unwrap();
+        assert!(poll(&mut tail).is_empty());
+        append(&path, "new\nhalf a ");
+        assert_eq!(poll(&mut tail), vec![(3, 10, String::from("new\n"))]);
+        assert!(poll(&mut tail).is_empty());
+        append(&path, "line\nand more\n");
+        assert_eq!(poll(&mut tail), vec![(4, 14, String::from("half a line\nand more\n"))]);
+    }
+
+    #[test]
+    fn finishes_a_line_left_unfinished_before_following_began() {
+        let path = scratch_file("unfinished", "old\nhalf a ");
+        let mut tail = Tail::new(&path).unwrap();
+        assert_eq!(tail.position(), Some((2, 4)));
+        assert!(poll(&mut tail).is_empty());
+        append(&path, "line\n");
+        assert_eq!(poll(&mut tail), vec![(2, 4, String::from("half a line\n"))]);
+    }
+
+    #[test]
+    fn starts_again_from_the_top_after_truncation() {
+        let path = scratch_file("truncate", "a long first line\n");
+        let mut tail = Tail::new(&path).unwrap();
+        fs::write(&path, "short\n").unwrap();
+        assert_eq!(poll(&mut tail), vec![(1, 0, String::from("short\n"))]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn finishes_the_old_file_then_follows_its_replacement() {
+        let path = scratch_file("rotate", "first\n");
+        let mut tail = Tail::new(&path).unwrap();
+        append(&path, "last words\nunfinished");
+        fs::rename(&path, path.with_extension("log.1")).unwrap();
+        assert_eq!(
+            poll(&mut tail),
+            vec![(2, 6, String::from("last words\nunfinished"))]
+        );
+        // nothing to follow until the new file turns up
+        assert!(poll(&mut tail).is_empty());
+        fs::write(&path, "fresh start\n").unwrap();
+        assert_eq!(poll(&mut tail), vec![(1, 0, String::from("fresh start\n"))]);
+    }
+
+    #[test]
+    fn waits_for_a_file_that_doesnt_exist_yet() {
+        let path = scratch_file("missing", "");
+        fs::remove_file(&path).unwrap();
+        let mut tail = Tail::new(&path).unwrap();
+        assert!(poll(&mut tail).is_empty());
+        fs::write(&path, "hello\n").unwrap();
+        assert_eq!(poll(&mut tail), vec![(1, 0, String::from("hello\n"))]);
+    }
+}
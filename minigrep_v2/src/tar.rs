@@ -0,0 +1,383 @@
+use std::io;
+use std::io::prelude::*;
+use std::str;
+
+// Archives are made of 512-byte blocks: a header for each member, then its
+// contents padded out to a whole number of blocks.
+const BLOCK: usize = 512;
+// Bigger than any sensible path, for pax and GNU headers whose contents
+// are read into memory.
+const MAX_EXTENSION: u64 = 1 << 20;
+
+/// Whether `reader` starts with a ustar header (which covers pax and GNU
+/// archives too), along with a reader that still starts from the top.
+///
+/// The header is read a piece at a time if need be, since pipes and
+/// decompressors often hand back less than a block at once.
+pub fn sniff<R: BufRead>(mut reader: R) -> io::Result<(bool, Sniffed<R>)> {
+    let mut header = Vec::with_capacity(BLOCK);
+    while header.len() < BLOCK {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        let len = buf.len().min(BLOCK - header.len());
+        header.extend_from_slice(&buf[..len]);
+        reader.consume(len);
+    }
+    let archive =
+        header.len() == BLOCK && header[257..262] == *b"ustar" && checksum_ok(&header);
+    Ok((archive, io::Cursor::new(header).chain(reader)))
+}
+
+/// A reader handed back by `sniff`: the header it read, then the rest.
+pub type Sniffed<R> = io::Chain<io::Cursor<Vec<u8>>, R>;
+
+/// A regular file in an archive.
+#[derive(Debug, PartialEq)]
+pub struct Member {
+    pub path: String,
+    pub size: u64,
+}
+
+/// Reads a tar archive one member at a time, skipping over anything that
+/// isn't a regular file (directories, links, devices, ...).
+///
+/// Reading from the `Archive` itself reads the contents of the member last
+/// returned by `next_member`.
+pub struct Archive<R> {
+    reader: R,
+    // how far into the archive we are, for error messages
+    offset: u64,
+    // what's left of the current member's contents, and the padding after
+    remaining: u64,
+    padding: u64,
+}
+
+impl<R: Read> Archive<R> {
+    pub fn new(reader: R) -> Self {
+        Archive {
+            reader,
+            offset: 0,
+            remaining: 0,
+            padding: 0,
+        }
+    }
+
+    /// Moves on to the next regular file, or returns `None` at the end of
+    /// the archive. Headers that don't add up are an `InvalidData` error.
+    pub fn next_member(&mut self) -> io::Result<Option<Member>> {
+        let rest = self.remaining + self.padding;
+        self.skip(rest)?;
+        self.remaining = 0;
+        self.padding = 0;
+
+        // what pax or GNU headers say about the member that follows them
+        let mut path = None;
+        let mut size = None;
+        loop {
+            let at = self.offset;
+            let mut header = [0; BLOCK];
+            if !self.read_block(&mut header)? || header.iter().all(|&b| b == 0) {
+                return Ok(None);
+            }
+            let corrupt = |reason: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("corrupt tar header at byte {}: {}", at, reason),
+                )
+            };
+            if !checksum_ok(&header) {
+                return Err(corrupt("bad checksum"));
+            }
+            let stored = number(&header[124..136]).ok_or_else(|| corrupt("bad size"))?;
+            let kind = header[156];
+            match kind {
+                // pax extended header for the next member
+                b'x' => {
+                    let records = self.read_extension(stored).map_err(|e| corrupt(&e))?;
+                    let records = pax_records(&records).ok_or_else(|| corrupt("bad pax record"))?;
+                    for (key, value) in records {
+                        match key {
+                            "path" => path = Some(value.to_string()),
+                            "size" => {
+                                size = Some(value.parse().map_err(|_| corrupt("bad pax size"))?)
+                            }
+                            _ => {}
+                        }
+                    }
+                    continue;
+                }
+                // GNU long name for the next member
+                b'L' => {
+                    let name = self.read_extension(stored).map_err(|e| corrupt(&e))?;
+                    path = Some(String::from_utf8_lossy(until_nul(&name)).into_owned());
+                    continue;
+                }
+                _ => {}
+            }
+            let size = size.take().unwrap_or(stored);
+            let padding = (BLOCK as u64 - size % BLOCK as u64) % BLOCK as u64;
+            // contiguous files are regular files as far as we're concerned
+            if kind != b'0' && kind != 0 && kind != b'7' {
+                self.skip(size + padding)?;
+                path = None;
+                continue;
+            }
+            let path = path.take().unwrap_or_else(|| header_path(&header));
+            self.remaining = size;
+            self.padding = padding;
+            return Ok(Some(Member { path, size }));
+        }
+    }
+
+    // Reads a whole block, or returns `false` if the archive just ends.
+    fn read_block(&mut self, block: &mut [u8; BLOCK]) -> io::Result<bool> {
+        let mut read = 0;
+        while read < BLOCK {
+            match self.reader.read(&mut block[read..]) {
+                Ok(0) if read == 0 => return Ok(false),
+                Ok(0) => return Err(truncated()),
+                Ok(n) => read += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.offset += BLOCK as u64;
+        Ok(true)
+    }
+
+    // The contents of a pax or GNU header, which are small enough to read
+    // into memory (or else something's wrong).
+    fn read_extension(&mut self, size: u64) -> Result<Vec<u8>, String> {
+        if size > MAX_EXTENSION {
+            return Err(format!("{} byte extended header", size));
+        }
+        let mut data = vec![0; size as usize];
+        self.reader.read_exact(&mut data).map_err(|_| String::from("truncated"))?;
+        self.offset += size;
+        let padding = (BLOCK as u64 - size % BLOCK as u64) % BLOCK as u64;
+        self.skip(padding).map_err(|e| e.to_string())?;
+        Ok(data)
+    }
+
+    fn skip(&mut self, n: u64) -> io::Result<()> {
+        let skipped = io::copy(&mut (&mut self.reader).take(n), &mut io::sink())?;
+        self.offset += skipped;
+        if skipped < n {
+            return Err(truncated());
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Archive<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max = buf.len().min(self.remaining.min(usize::MAX as u64) as usize);
+        if max == 0 {
+            return Ok(0);
+        }
+        let read = self.reader.read(&mut buf[..max])?;
+        if read == 0 {
+            return Err(truncated());
+        }
+        self.remaining -= read as u64;
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "tar archive is truncated")
+}
+
+// The checksum is the sum of the header's bytes, counting its own field as
+// spaces.
+fn checksum_ok(header: &[u8]) -> bool {
+    let sum: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| if (148..156).contains(&i) { b' ' } else { b } as u64)
+        .sum();
+    number(&header[148..156]) == Some(sum)
+}
+
+// A numeric field: octal digits padded with NULs or spaces, or for values
+// too big for that, big-endian base-256 flagged by the top bit.
+fn number(field: &[u8]) -> Option<u64> {
+    if field[0] & 0x80 != 0 {
+        let mut n: u64 = (field[0] & 0x7f) as u64;
+        for &b in &field[1..] {
+            n = n.checked_mul(256)? | b as u64;
+        }
+        return Some(n);
+    }
+    let digits = str::from_utf8(until_nul(field)).ok()?.trim();
+    if digits.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(digits, 8).ok()
+}
+
+// The member's path from the header itself: its name, after the prefix
+// that POSIX ustar headers (but not GNU ones) may split long paths into.
+fn header_path(header: &[u8; BLOCK]) -> String {
+    let name = until_nul(&header[..100]);
+    let posix = header[257..265] == *b"ustar\x0000";
+    let prefix = until_nul(&header[345..500]);
+    let path = if posix && !prefix.is_empty() {
+        [prefix, b"/", name].concat()
+    } else {
+        name.to_vec()
+    };
+    String::from_utf8_lossy(&path).into_owned()
+}
+
+// Parses `<length> <key>=<value>\n` records.
+fn pax_records(data: &[u8]) -> Option<Vec<(&str, &str)>> {
+    let mut records = Vec::new();
+    let mut rest = str::from_utf8(data).ok()?;
+    while !rest.is_empty() {
+        let space = rest.find(' ')?;
+        let len: usize = rest[..space].parse().ok()?;
+        let record = rest.get(space + 1..len)?.strip_suffix('\n')?;
+        let equals = record.find('=')?;
+        records.push((&record[..equals], &record[equals + 1..]));
+        rest = &rest[len..];
+    }
+    Some(records)
+}
+
+fn until_nul(bytes: &[u8]) -> &[u8] {
+    match bytes.iter().position(|&b| b == 0) {
+        Some(end) => &bytes[..end],
+        None => bytes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A ustar header for `name`, of type `kind` and with `size` bytes of
+    // contents.
+    fn header(name: &str, kind: u8, size: usize) -> Vec<u8> {
+        let mut header = vec![0; BLOCK];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..107].copy_from_slice(b"0000644");
+        header[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+        header[156] = kind;
+        header[257..265].copy_from_slice(b"ustar\x0000");
+        let sum: u32 = header.iter().map(|&b| b as u32).sum::<u32>() + 8 * b' ' as u32;
+        header[148..155].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+        header
+    }
+
+    fn entry(archive: &mut Vec<u8>, name: &str, kind: u8, contents: &[u8]) {
+        archive.extend(header(name, kind, contents.len()));
+        archive.extend(contents);
+        archive.resize(archive.len().div_ceil(BLOCK) * BLOCK, 0);
+    }
+
+    fn pax_record(key: &str, value: &str) -> String {
+        // the length counts its own digits too
+        let len = key.len() + value.len() + 3;
+        let len = len + (len + 1).to_string().len();
+        format!("{} {}={}\n", len, key, value)
+    }
+
+    fn members(archive: &[u8]) -> io::Result<Vec<(String, String)>> {
+        let mut archive = Archive::new(archive);
+        let mut members = Vec::new();
+        while let Some(member) = archive.next_member()? {
+            let mut contents = String::new();
+            archive.read_to_string(&mut contents)?;
+            members.push((member.path, contents));
+        }
+        Ok(members)
+    }
+
+    #[test]
+    fn reads_regular_files_and_skips_everything_else() {
+        let mut archive = Vec::new();
+        entry(&mut archive, "build/", b'5', b"");
+        entry(&mut archive, "build/log.txt", b'0', b"all good\n");
+        entry(&mut archive, "build/latest", b'2', b"");
+        entry(&mut archive, "build/big.bin", 0, &[7; 700]);
+        archive.extend([0; 2 * BLOCK]);
+
+        // however little is read at a time
+        let (found, mut reader) = sniff(io::BufReader::with_capacity(100, &archive[..])).unwrap();
+        assert!(found);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, archive);
+        let members = members(&archive).unwrap();
+        assert_eq!(members[0], (String::from("build/log.txt"), String::from("all good\n")));
+        assert_eq!(members[1].0, "build/big.bin");
+        assert_eq!(members[1].1.len(), 700);
+        assert_eq!(members.len(), 2);
+    }
+
+    #[test]
+    fn takes_long_paths_from_prefixes_and_pax_and_gnu_headers() {
+        let mut archive = Vec::new();
+        let mut prefixed = header("name.txt", b'0', 2);
+        prefixed[345..356].copy_from_slice(b"some/prefix");
+        let sum = prefixed.iter().map(|&b| b as u32).sum::<u32>()
+            - prefixed[148..156].iter().map(|&b| b as u32).sum::<u32>()
+            + 8 * b' ' as u32;
+        prefixed[148..155].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+        archive.extend(prefixed);
+        archive.extend(b"a\n");
+        archive.resize(2 * BLOCK, 0);
+
+        let long = format!("{}/file.txt", "deep".repeat(40));
+        let records = pax_record("path", &long) + &pax_record("mtime", "0");
+        entry(&mut archive, "PaxHeaders/file.txt", b'x', records.as_bytes());
+        entry(&mut archive, "truncated-name", b'0', b"b\n");
+        entry(&mut archive, "././@LongLink", b'L', b"gnu/long/name.txt\0");
+        entry(&mut archive, "gnu/long/na", b'0', b"c\n");
+
+        assert_eq!(
+            members(&archive).unwrap(),
+            vec![
+                (String::from("some/prefix/name.txt"), String::from("a\n")),
+                (long, String::from("b\n")),
+                (String::from("gnu/long/name.txt"), String::from("c\n")),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_corrupt_and_truncated_archives() {
+        let mut archive = Vec::new();
+        entry(&mut archive, "ok.txt", b'0', b"fine\n");
+        entry(&mut archive, "bad.txt", b'0', b"broken\n");
+        archive[BLOCK * 2 + 148] = b'7';
+        let mut reader = Archive::new(&archive[..]);
+        assert_eq!(reader.next_member().unwrap().unwrap().path, "ok.txt");
+        assert_eq!(
+            reader.next_member().unwrap_err().to_string(),
+            "corrupt tar header at byte 1024: bad checksum"
+        );
+
+        let mut archive = Vec::new();
+        entry(&mut archive, "cut.txt", b'0', &[b'x'; 600]);
+        archive.truncate(BLOCK + 100);
+        assert_eq!(
+            members(&archive).unwrap_err().to_string(),
+            "tar archive is truncated"
+        );
+        assert!(!sniff(&b"just text"[..]).unwrap().0);
+    }
+
+    #[test]
+    fn parses_octal_and_base256_numbers() {
+        assert_eq!(number(b"00000001750\0"), Some(1000));
+        assert_eq!(number(b"  1750 \0"), Some(1000));
+        assert_eq!(number(b"\0\0\0\0"), Some(0));
+        assert_eq!(number(b"\x80\0\0\0\0\0\0\x02\0\0\0\0"), Some(1 << 33));
+        assert_eq!(number(b"12x"), None);
+    }
+}
@@ -0,0 +1,364 @@
+use ignore::{Rules, Verdict};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Depth-first traversal yielding every regular file under a root path.
+///
+/// Entries are visited in file name order so output is stable across runs.
+/// The root is always followed even if it is a symlink; links found while
+/// descending are only followed when `follow_links` is set. A `max_depth`
+/// of `Some(0)` searches only the root itself, `Some(1)` its direct
+/// children, and so on.
+///
+/// Below the root, hidden entries (those whose names start with `.`) are
+/// skipped, as is anything matched by a `.gitignore` or `.ignore` file in
+/// the directories passed through on the way. Globs given with `globs`
+/// take precedence over both kinds of ignore file.
+pub struct Walk {
+    root: PathBuf,
+    stack: Vec<Entry>,
+    follow_links: bool,
+    max_depth: Option<usize>,
+    hidden: bool,
+    ignore_files: bool,
+    globs: Arc<Rules>,
+    visited: HashSet<PathBuf>,
+}
+
+// A path waiting to be visited, along with the ignore files that apply to it.
+struct Entry {
+    path: PathBuf,
+    depth: usize,
+    ignores: Option<Arc<Ignores>>,
+}
+
+// The rules from the ignore files of one directory, chained to those of the
+// directories above it.
+struct Ignores {
+    dir: PathBuf,
+    rules: Rules,
+    parent: Option<Arc<Ignores>>,
+}
+
+impl Walk {
+    pub fn new<P: AsRef<Path>>(root: P, follow_links: bool, max_depth: Option<usize>) -> Self {
+        let root = root.as_ref().to_path_buf();
+        Walk {
+            stack: vec![Entry {
+                path: root.clone(),
+                depth: 0,
+                ignores: None,
+            }],
+            root,
+            follow_links,
+            max_depth,
+            hidden: false,
+            ignore_files: true,
+            globs: Arc::new(Rules::default()),
+            visited: HashSet::new(),
+        }
+    }
+
+    /// Whether to search hidden files and directories too.
+    pub fn hidden(mut self, hidden: bool) -> Self {
+        self.hidden = hidden;
+        self
+    }
+
+    /// Whether to respect `.gitignore` and `.ignore` files.
+    pub fn ignore_files(mut self, ignore_files: bool) -> Self {
+        self.ignore_files = ignore_files;
+        self
+    }
+
+    /// Filters paths by glob, relative to the root.
+    pub fn globs(mut self, globs: Arc<Rules>) -> Self {
+        self.globs = globs;
+        self
+    }
+
+    // whether a path found below the root should be left out
+    fn is_skipped(&self, entry: &Entry, is_dir: bool) -> bool {
+        let hidden = entry
+            .path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if hidden && !self.hidden {
+            return true;
+        }
+        if !self.globs.is_empty() {
+            match self.globs.verdict(&relative(&self.root, &entry.path), is_dir) {
+                Verdict::Include => return false,
+                Verdict::Exclude => return true,
+                Verdict::Unmatched => {}
+            }
+        }
+        // the nearest ignore file with something to say about it decides
+        let mut ignores = entry.ignores.as_ref();
+        while let Some(level) = ignores {
+            match level.rules.verdict(&relative(&level.dir, &entry.path), is_dir) {
+                Verdict::Include => return false,
+                Verdict::Exclude => return true,
+                Verdict::Unmatched => ignores = level.parent.as_ref(),
+            }
+        }
+        false
+    }
+
+    fn descend(
+        &mut self,
+        dir: &Path,
+        depth: usize,
+        ignores: Option<Arc<Ignores>>,
+    ) -> io::Result<()> {
+        if self.max_depth.is_some_and(|max| depth >= max) {
+            return Ok(());
+        }
+        // following links can lead back into a directory we're already in
+        if !self.visited.insert(fs::canonicalize(dir)?) {
+            return Ok(());
+        }
+        let mut children = fs::read_dir(dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        children.sort();
+
+        let mut ignores = ignores;
+        if self.ignore_files {
+            // `.ignore` comes second so that its rules win
+            let text: String = [".gitignore", ".ignore"]
+                .iter()
+                .filter_map(|name| fs::read_to_string(dir.join(name)).ok())
+                .collect::<Vec<_>>()
+                .join("\n");
+            let rules = Rules::ignore_file(&text);
+            if !rules.is_empty() {
+                ignores = Some(Arc::new(Ignores {
+                    dir: dir.to_path_buf(),
+                    rules,
+                    parent: ignores,
+                }));
+            }
+        }
+        // pushed in reverse so the stack pops them in order
+        for child in children.into_iter().rev() {
+            self.stack.push(Entry {
+                path: child,
+                depth: depth + 1,
+                ignores: ignores.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+// `path` relative to `base`, spelled with `/` as ignore rules expect
+pub fn relative(base: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(base).unwrap_or(path);
+    let components: Vec<_> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    components.join("/")
+}
+
+impl Iterator for Walk {
+    type Item = io::Result<PathBuf>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(entry) = self.stack.pop() {
+            let (path, depth) = (&entry.path, entry.depth);
+            let meta = if depth == 0 || self.follow_links {
+                fs::metadata(path)
+            } else {
+                fs::symlink_metadata(path)
+            };
+            // roots are searched whatever their names, as they were asked for
+            if let Ok(ref meta) = meta {
+                if depth > 0 && self.is_skipped(&entry, meta.is_dir()) {
+                    continue;
+                }
+            }
+            let result = match meta {
+                Ok(ref meta) if meta.is_file() => return Some(Ok(entry.path)),
+                Ok(ref meta) if meta.is_dir() => self.descend(path, depth, entry.ignores.clone()),
+                // unfollowed symlinks, sockets, fifos, ...
+                Ok(_) => Ok(()),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                return Some(Err(io::Error::new(
+                    e.kind(),
+                    format!("{}: {}", path.display(), e),
+                )));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        common::fixture_dir(
+            &format!("walk-{}", name),
+            &[("top.txt", "top"), ("a/mid.txt", "mid"), ("a/b/deep.txt", "deep")],
+        )
+    }
+
+    fn relative(root: &Path, walk: Walk) -> Vec<String> {
+        walk.map(|p| {
+            p.unwrap()
+                .strip_prefix(root)
+                .unwrap()
+                .to_string_lossy()
+                .into_owned()
+        }).collect()
+    }
+
+    #[test]
+    fn walks_files_in_sorted_order() {
+        let root = scratch_dir("sorted");
+        assert_eq!(
+            relative(&root, Walk::new(&root, false, None)),
+            vec!["a/b/deep.txt", "a/mid.txt", "top.txt"]
+        );
+    }
+
+    #[test]
+    fn stops_at_max_depth() {
+        let root = scratch_dir("depth");
+        assert_eq!(relative(&root, Walk::new(&root, false, Some(1))), vec!["top.txt"]);
+        assert_eq!(
+            relative(&root, Walk::new(&root, false, Some(2))),
+            vec!["a/mid.txt", "top.txt"]
+        );
+    }
+
+    #[test]
+    fn yields_a_file_root_as_is() {
+        let root = scratch_dir("file");
+        let file = root.join("top.txt");
+        assert_eq!(
+            Walk::new(&file, false, Some(0)).map(|p| p.unwrap()).collect::<Vec<_>>(),
+            vec![file]
+        );
+    }
+
+    #[test]
+    fn skips_hidden_entries_unless_asked() {
+        let root = scratch_dir("hidden");
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::write(root.join(".git/HEAD"), "ref").unwrap();
+        fs::write(root.join("a/.env"), "secret").unwrap();
+
+        assert_eq!(
+            relative(&root, Walk::new(&root, false, None)),
+            vec!["a/b/deep.txt", "a/mid.txt", "top.txt"]
+        );
+        assert_eq!(
+            relative(&root, Walk::new(&root, false, None).hidden(true)),
+            vec![".git/HEAD", "a/.env", "a/b/deep.txt", "a/mid.txt", "top.txt"]
+        );
+        // named outright, a hidden path is searched anyway
+        let env = root.join("a/.env");
+        assert_eq!(Walk::new(&env, false, None).count(), 1);
+    }
+
+    #[test]
+    fn respects_nested_ignore_files() {
+        let root = scratch_dir("ignore");
+        fs::create_dir_all(root.join("target/debug")).unwrap();
+        fs::write(root.join("target/debug/out.txt"), "out").unwrap();
+        fs::write(root.join("a/keep.log"), "log").unwrap();
+        fs::write(root.join("a/b/drop.log"), "log").unwrap();
+        fs::write(root.join(".gitignore"), "target/\n*.log\n").unwrap();
+        // deeper files override shallower ones, and `.ignore` overrides
+        // `.gitignore` in the same directory
+        fs::write(root.join("a/.gitignore"), "!*.log\nmid.txt\n").unwrap();
+        fs::write(root.join("a/.ignore"), "b/drop.log\n").unwrap();
+
+        assert_eq!(
+            relative(&root, Walk::new(&root, false, None)),
+            vec!["a/b/deep.txt", "a/keep.log", "top.txt"]
+        );
+        assert_eq!(
+            relative(&root, Walk::new(&root, false, None).ignore_files(false)),
+            vec![
+                "a/b/deep.txt",
+                "a/b/drop.log",
+                "a/keep.log",
+                "a/mid.txt",
+                "target/debug/out.txt",
+                "top.txt",
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_by_glob_ahead_of_ignore_files() {
+        let root = scratch_dir("globs");
+        fs::write(root.join("a/b/deep.rs"), "rs").unwrap();
+        fs::write(root.join("Cargo.lock"), "lock").unwrap();
+        fs::write(root.join(".ignore"), "*.txt\n").unwrap();
+        let globs = |globs: &[&str]| {
+            let globs: Vec<String> = globs.iter().map(|g| g.to_string()).collect();
+            Arc::new(Rules::globs(&globs).unwrap())
+        };
+
+        assert_eq!(
+            relative(&root, Walk::new(&root, false, None).globs(globs(&["!*.lock"]))),
+            vec!["a/b/deep.rs"]
+        );
+        assert_eq!(
+            relative(&root, Walk::new(&root, false, None).globs(globs(&["*.rs", "top.*"]))),
+            vec!["a/b/deep.rs", "top.txt"]
+        );
+        assert_eq!(
+            relative(&root, Walk::new(&root, false, None).globs(globs(&["!a"]))),
+            vec!["Cargo.lock"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follows_symlinks_only_when_asked() {
+        use std::os::unix::fs::symlink;
+
+        let root = scratch_dir("links");
+        let outside = root.with_extension("outside");
+        let _ = fs::remove_dir_all(&outside);
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("far.txt"), "far").unwrap();
+        symlink(&outside, root.join("z")).unwrap();
+
+        assert_eq!(
+            relative(&root, Walk::new(&root, false, None)),
+            vec!["a/b/deep.txt", "a/mid.txt", "top.txt"]
+        );
+        assert_eq!(
+            relative(&root, Walk::new(&root, true, None)),
+            vec!["a/b/deep.txt", "a/mid.txt", "top.txt", "z/far.txt"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn survives_symlink_loops() {
+        use std::os::unix::fs::symlink;
+
+        let root = scratch_dir("loops");
+        symlink(root.join("a"), root.join("a/b/loop")).unwrap();
+
+        assert_eq!(
+            relative(&root, Walk::new(&root, true, None)),
+            vec!["a/b/deep.txt", "a/mid.txt", "top.txt"]
+        );
+    }
+}
@@ -1,13 +1,24 @@
 extern crate minigrep_v2;
-use minigrep_v2::Config;
+mod common;
+
+use minigrep_v2::{ColorChoice, Config, Match, MatcherKind, OutputMode, Sort};
+use std::fs;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::process::{Command, Output, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 
 #[test]
 fn performs_case_sensitive_search() {
     assert_eq!(
         minigrep_v2::run(Config {
-            query: String::from("to"),
-            path: String::from("poem.txt"),
+            patterns: vec![String::from("to")],
+            paths: vec![String::from("poem.txt")],
             case_sensitive: true,
+            ..Config::default()
         }).unwrap(),
         vec![
             String::from("Are you nobody, too?"),
@@ -20,9 +31,10 @@ fn performs_case_sensitive_search() {
 fn performs_case_insensitive_search() {
     assert_eq!(
         minigrep_v2::run(Config {
-            query: String::from("to"),
-            path: String::from("poem.txt"),
+            patterns: vec![String::from("to")],
+            paths: vec![String::from("poem.txt")],
             case_sensitive: false,
+            ..Config::default()
         }).unwrap(),
         vec![
             String::from("Are you nobody, too?"),
@@ -32,3 +44,962 @@ fn performs_case_insensitive_search() {
         ]
     )
 }
+
+#[test]
+fn matches_whole_words() {
+    assert_eq!(
+        minigrep_v2::run(Config {
+            patterns: vec![String::from("to")],
+            paths: vec![String::from("poem.txt")],
+            case_sensitive: false,
+            word_regexp: true,
+            ..Config::default()
+        }).unwrap(),
+        vec![
+            String::from("How dreary to be somebody!"),
+            String::from("To tell your name the livelong day"),
+            String::from("To an admiring bog!"),
+        ]
+    )
+}
+
+#[test]
+fn matches_whole_lines() {
+    assert_eq!(
+        minigrep_v2::run(Config {
+            patterns: vec![String::from(r"to \w+ \w+ bog!")],
+            paths: vec![String::from("poem.txt")],
+            case_sensitive: false,
+            matcher: MatcherKind::Regex,
+            line_regexp: true,
+            ..Config::default()
+        }).unwrap(),
+        vec![String::from("To an admiring bog!")]
+    )
+}
+
+#[test]
+fn searches_for_several_patterns_from_the_command_line_and_files() {
+    let dir = common::fixture_dir("patterns", &[("denylist.txt", "frog\nbog\n")]);
+    let denylist = dir.join("denylist.txt");
+    let output = Command::new(env!("CARGO_BIN_EXE_minigrep_v2"))
+        .args(["-n", "-e", "nobody!", "-f", denylist.to_str().unwrap(), "poem.txt"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "1:I’m nobody! Who are you?\n7:How public, like a frog\n9:To an admiring bog!\n"
+    );
+}
+
+#[test]
+fn performs_regex_search() {
+    assert_eq!(
+        minigrep_v2::run(Config {
+            patterns: vec![String::from(r"^To .*day$|\bnobody\b")],
+            paths: vec![String::from("poem.txt")],
+            matcher: MatcherKind::Regex,
+            ..Config::default()
+        }).unwrap(),
+        vec![
+            String::from("I’m nobody! Who are you?"),
+            String::from("Are you nobody, too?"),
+            String::from("To tell your name the livelong day"),
+        ]
+    )
+}
+
+#[test]
+fn reports_invalid_regex() {
+    let err = minigrep_v2::run(Config {
+        patterns: vec![String::from("[a-")],
+        paths: vec![String::from("poem.txt")],
+        matcher: MatcherKind::Regex,
+        ..Config::default()
+    }).unwrap_err();
+    assert!(err.to_string().starts_with("invalid pattern `[a-`:"));
+}
+
+#[test]
+fn searches_directories_recursively() {
+    let dir = common::fixture_dir(
+        "recursive",
+        &[
+            ("notes.txt", "nobody here\n"),
+            ("poems/dickinson.txt", "I'm nobody! Who are you?\nAre you nobody, too?\n"),
+            ("poems/deeper/frost.txt", "Whose woods these are\nnobody knows\n"),
+        ],
+    );
+    let root = dir.to_str().unwrap().to_string();
+    let prefixed = |path: &str, line: &str| format!("{}/{}:{}", root, path, line);
+
+    assert_eq!(
+        minigrep_v2::run(Config {
+            patterns: vec![String::from("nobody")],
+            paths: vec![root.clone()],
+            sort: Sort::Path,
+            ..Config::default()
+        }).unwrap(),
+        vec![
+            prefixed("notes.txt", "nobody here"),
+            prefixed("poems/deeper/frost.txt", "nobody knows"),
+            prefixed("poems/dickinson.txt", "I'm nobody! Who are you?"),
+            prefixed("poems/dickinson.txt", "Are you nobody, too?"),
+        ]
+    );
+
+    assert_eq!(
+        minigrep_v2::run(Config {
+            patterns: vec![String::from("nobody")],
+            paths: vec![root.clone()],
+            sort: Sort::Path,
+            max_depth: Some(2),
+            ..Config::default()
+        }).unwrap(),
+        vec![
+            prefixed("notes.txt", "nobody here"),
+            prefixed("poems/dickinson.txt", "I'm nobody! Who are you?"),
+            prefixed("poems/dickinson.txt", "Are you nobody, too?"),
+        ]
+    );
+}
+
+#[test]
+fn skips_ignored_and_hidden_files_while_recursing() {
+    let dir = common::fixture_dir(
+        "ignored",
+        &[
+            (".gitignore", "target/\n*.orig\n"),
+            (".git/config", "nobody = true\n"),
+            ("src/main.rs", "// nobody here\n"),
+            ("src/main.rs.orig", "// nobody was here\n"),
+            ("target/debug/build.log", "nobody built this\n"),
+            ("Cargo.lock", "nobody locked this\n"),
+            ("notes.txt", "nobody reads notes\n"),
+        ],
+    );
+    let search = |globs: &[&str]| {
+        let mut args = vec!["--sort=path", "nobody"];
+        for glob in globs {
+            args.extend_from_slice(&["--glob", glob]);
+        }
+        let output = Command::new(env!("CARGO_BIN_EXE_minigrep_v2"))
+            .args(&args)
+            .arg(&dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let root = format!("{}/", dir.display());
+        String::from_utf8(output.stdout).unwrap().replace(&root, "")
+    };
+
+    assert_eq!(
+        search(&[]),
+        "Cargo.lock:nobody locked this\nnotes.txt:nobody reads notes\nsrc/main.rs:// nobody here\n"
+    );
+    assert_eq!(search(&["*.rs"]), "src/main.rs:// nobody here\n");
+    assert_eq!(
+        search(&["!*.lock", "!notes.*"]),
+        "src/main.rs:// nobody here\n"
+    );
+}
+
+#[test]
+fn searches_multiple_files_in_order_with_prefixes() {
+    let dir = common::fixture_dir(
+        "multiple",
+        &[("a.txt", "frog\ntoad\n"), ("b.txt", "no match\n"), ("c.txt", "bog frog\n")],
+    );
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+    assert_eq!(
+        minigrep_v2::run(Config {
+            patterns: vec![String::from("frog")],
+            paths: vec![path("c.txt"), path("b.txt"), path("a.txt")],
+            sort: Sort::Path,
+            ..Config::default()
+        }).unwrap(),
+        vec![format!("{}:bog frog", path("c.txt")), format!("{}:frog", path("a.txt"))]
+    );
+}
+
+// A tree of files with uneven amounts of matching text, so that a pool of
+// threads finishes them in a different order than they were handed out.
+fn uneven_tree(name: &str) -> String {
+    let files: Vec<(String, String)> = (0..40)
+        .map(|n| {
+            let lines = if n % 7 == 0 { 20_000 } else { n + 1 };
+            let contents: String = (0..lines)
+                .map(|line| format!("file {} line {} of frogs and toads\n", n, line))
+                .collect();
+            (format!("dir{}/file{:02}.txt", n % 3, n), contents)
+        }).collect();
+    let files: Vec<(&str, &str)> = files.iter().map(|(p, c)| (p.as_str(), c.as_str())).collect();
+    common::fixture_dir(name, &files).to_str().unwrap().to_string()
+}
+
+fn search_tree(root: &str, threads: usize, sort: Sort) -> Vec<u8> {
+    let mut out = Vec::new();
+    minigrep_v2::run_to(
+        &Config {
+            patterns: vec![String::from("9 of frogs")],
+            paths: vec![root.to_string(), String::from("poem.txt")],
+            line_number: true,
+            before_context: 1,
+            threads,
+            sort,
+            ..Config::default()
+        },
+        &mut out,
+    ).unwrap();
+    out
+}
+
+#[test]
+fn prints_the_same_in_parallel_when_sorted_by_path() {
+    let root = uneven_tree("parallel-sorted");
+    let single = search_tree(&root, 1, Sort::Unsorted);
+    assert!(!single.is_empty());
+    for &threads in &[2, 4, 8] {
+        assert_eq!(search_tree(&root, threads, Sort::Path), single);
+    }
+}
+
+#[test]
+fn keeps_each_files_results_together_when_unsorted() {
+    let root = uneven_tree("parallel-unsorted");
+    // one block per file, each of them starting with its own path
+    let blocks = |out: Vec<u8>| {
+        let out = String::from_utf8(out).unwrap();
+        let mut blocks: Vec<String> = Vec::new();
+        // `--` separators depend on which file came before, so leave them out
+        for line in out.lines().filter(|&line| line != "--") {
+            let path = &line[..line.find(".txt").unwrap()];
+            match blocks.last_mut() {
+                Some(block) if block.starts_with(path) => {
+                    block.push_str(line);
+                    block.push('\n');
+                }
+                _ => blocks.push(format!("{}\n", line)),
+            }
+        }
+        blocks.sort();
+        blocks
+    };
+    let single = blocks(search_tree(&root, 1, Sort::Unsorted));
+    assert_eq!(blocks(search_tree(&root, 4, Sort::Unsorted)), single);
+}
+
+#[test]
+fn keeps_searching_past_unreadable_files() {
+    let dir = common::fixture_dir("unreadable", &[("a.txt", "frog\n")]);
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_minigrep_v2"))
+        .args(["frog", &path("missing.txt"), &path("a.txt"), &path("gone.txt")])
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("{}:frog\n", path("a.txt"))
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(&format!("{}: ", path("missing.txt"))));
+    assert!(stderr.contains(&format!("{}: ", path("gone.txt"))));
+    assert!(stderr.contains("2 file(s) could not be searched"));
+}
+
+#[test]
+fn finds_structured_matches() {
+    let matches = minigrep_v2::find_matches(&Config {
+        patterns: vec![String::from("you")],
+        paths: vec![String::from("poem.txt")],
+        ..Config::default()
+    }).unwrap();
+    assert_eq!(
+        matches,
+        vec![
+            Match {
+                path: String::from("poem.txt"),
+                line_number: 1,
+                byte_offset: 0,
+                line: "I’m nobody! Who are you?".as_bytes().to_vec(),
+                spans: vec![(22, 25)],
+                distances: vec![],
+            },
+            Match {
+                path: String::from("poem.txt"),
+                line_number: 2,
+                byte_offset: 27,
+                line: b"Are you nobody, too?".to_vec(),
+                spans: vec![(4, 7)],
+                distances: vec![],
+            },
+            Match {
+                path: String::from("poem.txt"),
+                line_number: 4,
+                byte_offset: 94,
+                line: "They’d banish us, you know.".as_bytes().to_vec(),
+                spans: vec![(20, 23)],
+                distances: vec![],
+            },
+            Match {
+                path: String::from("poem.txt"),
+                line_number: 8,
+                byte_offset: 176,
+                line: b"To tell your name the livelong day".to_vec(),
+                spans: vec![(8, 11)],
+                distances: vec![],
+            },
+        ]
+    );
+}
+
+#[test]
+fn prints_line_numbers_and_byte_offsets() {
+    assert_eq!(
+        minigrep_v2::run(Config {
+            patterns: vec![String::from("bog")],
+            paths: vec![String::from("poem.txt")],
+            line_number: true,
+            byte_offset: true,
+            ..Config::default()
+        }).unwrap(),
+        vec![String::from("9:211:To an admiring bog!")]
+    )
+}
+
+#[test]
+fn prints_context_around_matches() {
+    assert_eq!(
+        minigrep_v2::run(Config {
+            patterns: vec![String::from("frog")],
+            paths: vec![String::from("poem.txt")],
+            line_number: true,
+            before_context: 2,
+            after_context: 1,
+            ..Config::default()
+        }).unwrap(),
+        vec![
+            String::from("5-"),
+            String::from("6-How dreary to be somebody!"),
+            String::from("7:How public, like a frog"),
+            String::from("8-To tell your name the livelong day"),
+        ]
+    );
+    assert_eq!(
+        minigrep_v2::run(Config {
+            patterns: vec![String::from("body")],
+            paths: vec![String::from("poem.txt")],
+            line_number: true,
+            before_context: 1,
+            after_context: 1,
+            ..Config::default()
+        }).unwrap(),
+        vec![
+            String::from("1:I’m nobody! Who are you?"),
+            String::from("2:Are you nobody, too?"),
+            String::from("3-Then there’s a pair of us — don’t tell!"),
+            String::from("--"),
+            String::from("5-"),
+            String::from("6:How dreary to be somebody!"),
+            String::from("7-How public, like a frog"),
+        ]
+    );
+}
+
+#[test]
+fn prints_non_matching_lines_when_inverted() {
+    assert_eq!(
+        minigrep_v2::run(Config {
+            patterns: vec![String::from("o")],
+            paths: vec![String::from("poem.txt")],
+            invert_match: true,
+            ..Config::default()
+        }).unwrap(),
+        vec![String::from("")]
+    )
+}
+
+#[test]
+fn prints_per_file_counts_and_file_lists() {
+    let dir = common::fixture_dir(
+        "modes",
+        &[("a.txt", "frog\ntoad\nfrog\n"), ("b.txt", "newt\n")],
+    );
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+    let run = |output, invert_match| {
+        minigrep_v2::run(Config {
+            patterns: vec![String::from("frog")],
+            paths: vec![path("a.txt"), path("b.txt")],
+            sort: Sort::Path,
+            invert_match,
+            output,
+            ..Config::default()
+        }).unwrap()
+    };
+
+    assert_eq!(
+        run(OutputMode::Count, false),
+        vec![format!("{}:2", path("a.txt")), format!("{}:0", path("b.txt"))]
+    );
+    assert_eq!(
+        run(OutputMode::Count, true),
+        vec![format!("{}:1", path("a.txt")), format!("{}:1", path("b.txt"))]
+    );
+    assert_eq!(run(OutputMode::FilesWithMatches, false), vec![path("a.txt")]);
+    assert_eq!(run(OutputMode::FilesWithoutMatch, false), vec![path("b.txt")]);
+}
+
+#[test]
+fn streams_through_large_files_without_requiring_utf8() {
+    let dir = common::fixture_dir("large", &[]);
+    let path = dir.join("big.log");
+    {
+        let mut file = BufWriter::new(File::create(&path).unwrap());
+        for i in 0..200_000 {
+            if i % 50_000 == 49_999 {
+                file.write_all(b"\xff\xfeERROR: disk on fire\n").unwrap();
+            } else {
+                writeln!(file, "{:06} INFO all quiet", i).unwrap();
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    minigrep_v2::run_to(
+        &Config {
+            patterns: vec![String::from("ERROR")],
+            paths: vec![path.to_str().unwrap().to_string()],
+            line_number: true,
+            ..Config::default()
+        },
+        &mut out,
+    ).unwrap();
+
+    let mut expected = Vec::new();
+    for n in &[50_000, 100_000, 150_000, 200_000] {
+        expected.extend(format!("{}:", n).into_bytes());
+        expected.extend(b"\xff\xfeERROR: disk on fire\n");
+    }
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn streams_matches_as_they_are_found_on_several_threads() {
+    let dir = common::fixture_dir("streaming", &[]);
+    let big = dir.join("big.log");
+    {
+        let mut file = BufWriter::new(File::create(&big).unwrap());
+        for i in 0..100_000 {
+            writeln!(file, "{:06} {}", i, if i % 25_000 == 0 { "ERROR" } else { "INFO" }).unwrap();
+        }
+    }
+    // the default number of threads, and enough to be sure of the pool on
+    // machines with only one processor
+    let runs = [
+        ("--sort=none", None),
+        ("--sort=path", None),
+        ("--sort=none", Some("-j4")),
+        ("--sort=path", Some("-j4")),
+    ];
+    for &(sort, threads) in &runs {
+        // standard input is searched alongside a large file, and has to
+        // come out while it's still being written
+        let mut child = Command::new(env!("CARGO_BIN_EXE_minigrep_v2"))
+            .args(threads)
+            .args([sort, "ERROR", "-", big.to_str().unwrap()])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let (sender, lines) = mpsc::channel();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        thread::spawn(move || {
+            for line in stdout.lines() {
+                if sender.send(line.unwrap()).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(b"ERROR from a pipe\n").unwrap();
+        let mut seen = Vec::new();
+        while !seen.contains(&String::from("(standard input):ERROR from a pipe")) {
+            seen.push(lines.recv_timeout(Duration::from_secs(10)).unwrap());
+        }
+        drop(stdin);
+        seen.extend(lines.iter());
+        assert!(child.wait().unwrap().success());
+
+        let mut expected = vec![String::from("(standard input):ERROR from a pipe")];
+        for i in &[0, 25_000, 50_000, 75_000] {
+            expected.push(format!("{}:{:06} ERROR", big.display(), i));
+        }
+        if sort == "--sort=none" {
+            seen.sort();
+            expected.sort();
+        }
+        assert_eq!(seen, expected);
+    }
+}
+
+#[test]
+fn summarizes_skips_or_prints_binary_files() {
+    let dir = common::fixture_dir("binary", &[("a.txt", "frog\n")]);
+    fs::write(dir.join("b.bin"), b"\x7fELF\0\0frog\n\0frog\n").unwrap();
+    let search = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_minigrep_v2"))
+            .args(["--sort=path"])
+            .args(args)
+            .arg(&dir)
+            .output()
+            .unwrap();
+        let root = format!("{}/", dir.display());
+        String::from_utf8(output.stdout).unwrap().replace(&root, "")
+    };
+
+    assert_eq!(search(&["frog"]), "a.txt:frog\nBinary file b.bin matches\n");
+    assert_eq!(search(&["--binary-skip", "frog"]), "a.txt:frog\n");
+    assert_eq!(
+        search(&["--text", "frog"]),
+        "a.txt:frog\nb.bin:\x7fELF\0\0frog\nb.bin:\0frog\n"
+    );
+    assert_eq!(search(&["-c", "frog"]), "a.txt:1\nb.bin:2\n");
+}
+
+#[test]
+fn transcodes_other_encodings_but_reports_original_offsets() {
+    let dir = common::fixture_dir("encodings", &[]);
+    let mut utf16 = vec![0xff, 0xfe];
+    for unit in "first line\nnaïve café\n".encode_utf16() {
+        utf16.extend_from_slice(&unit.to_le_bytes());
+    }
+    fs::write(dir.join("utf16.txt"), utf16).unwrap();
+    fs::write(dir.join("latin1.txt"), b"caf\xe9\nna\xefve caf\xe9\n").unwrap();
+    let search = |args: &[&str], file: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_minigrep_v2"))
+            .args(args)
+            .arg(dir.join(file))
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    // a byte order mark is enough to go on
+    assert_eq!(search(&["-nb", "café"], "utf16.txt"), "2:24:naïve café\n");
+    // without one, the encoding has to be given
+    assert_eq!(search(&["-nb", "café"], "latin1.txt"), "");
+    assert_eq!(
+        search(&["-nb", "--encoding=latin1", "naïve"], "latin1.txt"),
+        "2:5:naïve café\n"
+    );
+}
+
+#[test]
+fn prints_json_lines_that_survive_awkward_paths_and_bytes() {
+    let dir = common::fixture_dir("json", &[("odd: name.txt", "say \"frog\"\tnow\n")]);
+    fs::write(dir.join("raw.bin"), b"\xff frog\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_minigrep_v2"))
+        .args(["--json", "--sort=path", "frog"])
+        .arg(&dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let root = format!("{}/", dir.display());
+    let stdout = String::from_utf8(output.stdout).unwrap().replace(&root, "");
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        vec![
+            r#"{"type":"begin","data":{"path":{"text":"odd: name.txt"}}}"#,
+            r#"{"type":"match","data":{"path":{"text":"odd: name.txt"},"lines":{"text":"say \"frog\"\tnow"},"line_number":1,"absolute_offset":0,"submatches":[{"match":{"text":"frog"},"start":5,"end":9}]}}"#,
+            r#"{"type":"end","data":{"path":{"text":"odd: name.txt"},"binary":false,"stats":{"matched_lines":1,"matches":1}}}"#,
+            r#"{"type":"begin","data":{"path":{"text":"raw.bin"}}}"#,
+            r#"{"type":"match","data":{"path":{"text":"raw.bin"},"lines":{"bytes":"/yBmcm9n"},"line_number":1,"absolute_offset":0,"submatches":[{"match":{"text":"frog"},"start":2,"end":6}]}}"#,
+            r#"{"type":"end","data":{"path":{"text":"raw.bin"},"binary":false,"stats":{"matched_lines":1,"matches":1}}}"#,
+        ]
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn prints_json_paths_that_arent_utf8_as_bytes() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = common::fixture_dir("json-raw-path", &[]);
+    let name = OsStr::from_bytes(b"a\xffb.txt");
+    fs::write(dir.join(name), "frog\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_minigrep_v2"))
+        .args(["--json", "frog", "."])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    // "Li9h/2IudHh0" is "./a\xffb.txt"
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        vec![
+            r#"{"type":"begin","data":{"path":{"bytes":"Li9h/2IudHh0"}}}"#,
+            r#"{"type":"match","data":{"path":{"bytes":"Li9h/2IudHh0"},"lines":{"text":"frog"},"line_number":1,"absolute_offset":0,"submatches":[{"match":{"text":"frog"},"start":0,"end":4}]}}"#,
+            r#"{"type":"end","data":{"path":{"bytes":"Li9h/2IudHh0"},"binary":false,"stats":{"matched_lines":1,"matches":1}}}"#,
+        ]
+    );
+}
+
+fn run_with_stdin(args: &[&str], envs: &[(&str, &str)], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_minigrep_v2"))
+        .args(args)
+        .envs(envs.iter().cloned())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn reads_standard_input_when_no_path_is_given() {
+    let output = run_with_stdin(&["-n", "error"], &[], b"ok\nerror: one\nERROR: two\n");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"2:error: one\n".to_vec());
+
+    let output = run_with_stdin(
+        &["error"],
+        &[("CASE_INSENSITIVE", "1")],
+        b"ok\nerror: one\nERROR: two\n",
+    );
+    assert_eq!(output.stdout, b"error: one\nERROR: two\n".to_vec());
+}
+
+#[test]
+fn reads_standard_input_for_a_dash_among_other_paths() {
+    let output = run_with_stdin(
+        &["--sort=path", "-c", "frog", "poem.txt", "-"],
+        &[],
+        b"frog\nfrog\n\xff\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "poem.txt:1\n(standard input):2\n"
+    );
+}
+
+#[test]
+fn highlights_case_insensitive_matches_at_their_original_offsets() {
+    // `İ` is two bytes but lowercases to three, so offsets into a lowercased
+    // copy of this line would land one byte too far to the right
+    let dir = common::fixture_dir("color", &[("cities.txt", "İSTANBUL or Istanbul\n")]);
+    let mut out = Vec::new();
+    minigrep_v2::run_to(
+        &Config {
+            patterns: vec![String::from("istanbul")],
+            paths: vec![dir.join("cities.txt").to_str().unwrap().to_string()],
+            case_sensitive: false,
+            color: ColorChoice::Always,
+            ..Config::default()
+        },
+        &mut out,
+    ).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "İSTANBUL or \x1b[1;31mIstanbul\x1b[0m\n"
+    );
+}
+
+#[test]
+fn only_colors_when_asked_or_on_a_terminal() {
+    let output = run_with_stdin(&["frog"], &[], b"a frog\n");
+    assert_eq!(output.stdout, b"a frog\n".to_vec());
+
+    let output = run_with_stdin(&["--color=always", "frog"], &[], b"a frog\n");
+    assert_eq!(output.stdout, b"a \x1b[1;31mfrog\x1b[0m\n".to_vec());
+}
+
+#[test]
+fn matches_typed_apostrophes_against_curly_ones_when_folding() {
+    assert_eq!(
+        minigrep_v2::run(Config {
+            patterns: vec![String::from("DON'T TELL")],
+            paths: vec![String::from("poem.txt")],
+            case_fold: true,
+            color: ColorChoice::Always,
+            ..Config::default()
+        }).unwrap(),
+        vec![String::from(
+            "Then there’s a pair of us — \x1b[1;31mdon’t tell\x1b[0m!"
+        )]
+    )
+}
+
+#[test]
+fn previews_replacements_then_makes_them_in_place() {
+    let dir = common::fixture_dir(
+        "replace",
+        &[("a.txt", "mail emily@amherst\r\nor ned@home\nthanks\n"), ("b.txt", "nothing\n")],
+    );
+    fs::write(dir.join("c.bin"), b"emily@amherst\0").unwrap();
+    let replace = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_minigrep_v2"))
+            .args(["--sort=path", "-E", r"(\w+)@(\w+)", "-r", "$2!$1"])
+            .args(args)
+            .arg(&dir)
+            .output()
+            .unwrap();
+        let root = format!("{}/", dir.display());
+        (
+            String::from_utf8(output.stdout).unwrap().replace(&root, ""),
+            String::from_utf8(output.stderr).unwrap().replace(&root, ""),
+        )
+    };
+
+    let (stdout, stderr) = replace(&[]);
+    assert_eq!(
+        stdout,
+        "--- a.txt\n+++ a.txt\n@@ -1,2 +1,2 @@\n\
+         -mail emily@amherst\n-or ned@home\n+mail amherst!emily\n+or home!ned\n"
+    );
+    assert!(stderr.starts_with("c.bin: binary file, not replacing\n"));
+    assert_eq!(
+        fs::read_to_string(dir.join("a.txt")).unwrap(),
+        "mail emily@amherst\r\nor ned@home\nthanks\n"
+    );
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir.join("a.txt"), fs::Permissions::from_mode(0o640)).unwrap();
+    }
+    let (stdout, stderr) = replace(&["--in-place", "--binary-skip"]);
+    assert_eq!((stdout.as_str(), stderr.as_str()), ("", ""));
+    assert_eq!(
+        fs::read_to_string(dir.join("a.txt")).unwrap(),
+        "mail amherst!emily\r\nor home!ned\nthanks\n"
+    );
+    assert_eq!(fs::read(dir.join("c.bin")).unwrap(), b"emily@amherst\0".to_vec());
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(dir.join("a.txt")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+    }
+    // nothing left over from the temporary files
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+}
+
+#[test]
+fn finds_typos_within_an_edit_distance() {
+    let dir = common::fixture_dir(
+        "fuzzy",
+        &[("log.txt", "user nobdy logged in\nuser noboy out\n")],
+    );
+    let matches = minigrep_v2::find_matches(&Config {
+        patterns: vec![String::from("nobody")],
+        paths: vec![dir.join("log.txt").display().to_string()],
+        fuzzy: Some(1),
+        ..Config::default()
+    }).unwrap();
+    let found: Vec<_> = matches
+        .iter()
+        .map(|m| (m.line_number, m.spans.clone(), m.distances.clone()))
+        .collect();
+    assert_eq!(found, vec![(1, vec![(5, 10)], vec![1]), (2, vec![(5, 10)], vec![1])]);
+
+    let output = run_with_stdin(
+        &["--fuzzy=1", "-i", "NOBODY"],
+        &[],
+        b"Are you nobody, too?\nnope\n",
+    );
+    assert_eq!(output.stdout, b"Are you nobody, too?\n".to_vec());
+}
+
+#[test]
+fn matches_patterns_that_span_lines() {
+    let source = b"fn search(\n    query: &str,\n) -> bool {\n    true\n}\n";
+    let output = run_with_stdin(&["-nUE", r"fn \w+\(\n\s+query"], &[], source);
+    assert_eq!(output.stdout, b"1:fn search(\n2:    query: &str,\n".to_vec());
+
+    // without -U, lines are matched one by one and the pattern can't
+    let output = run_with_stdin(&["-nE", r"fn \w+\(\n\s+query"], &[], source);
+    assert_eq!(output.stdout, b"".to_vec());
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn searches_gzipped_files_like_their_decompressed_text() {
+    let search = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_minigrep_v2"))
+            .args(args)
+            .output()
+            .unwrap();
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    let plain = search(&["-nb", "-C1", "you", "poem.txt"]);
+    assert_eq!(search(&["-znb", "-C1", "you", "tests/fixtures/poem.txt.gz"]), plain);
+    // files that aren't compressed are searched as they are
+    assert_eq!(search(&["-znb", "-C1", "you", "poem.txt"]), plain);
+    // and compressed ones look binary without -z
+    assert_eq!(search(&["-c", "you", "tests/fixtures/poem.txt.gz"]), "0\n");
+
+    assert_eq!(
+        search(&["-z", "--sort=path", "ERROR", "tests/fixtures"]),
+        "tests/fixtures/rotated.log.gz:2024-05-01 09:00:02 ERROR disk full\n\
+         tests/fixtures/rotated.log.gz:2024-05-02 10:11:13 ERROR gave up\n"
+    );
+}
+
+#[test]
+fn searches_tar_archives_member_by_member() {
+    let search = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_minigrep_v2"))
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let nested = "nested/".repeat(12);
+
+    let output = search(&["--archives", "-n", "warning", "tests/fixtures/bundle.tar"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!(
+            "tests/fixtures/bundle.tar:build/log.txt:2:warning: unused variable\n\
+             tests/fixtures/bundle.tar:build/reports/{}summary.txt:2:warning: slow test\n",
+            nested
+        )
+    );
+    // without --archives it's just a binary file
+    let output = search(&["warning", "tests/fixtures/bundle.tar"]);
+    assert_eq!(output.stdout, b"Binary file tests/fixtures/bundle.tar matches\n".to_vec());
+
+    // a bad header fails that archive, after the members before it
+    let dir = common::fixture_dir("corrupt-tar", &[("notes.txt", "warning: none\n")]);
+    let mut bundle = fs::read("tests/fixtures/bundle.tar").unwrap();
+    bundle[6 * 512 + 148] ^= 1;
+    fs::write(dir.join("bad.tar"), bundle).unwrap();
+    let bad = dir.join("bad.tar").to_str().unwrap().to_string();
+    let notes = dir.join("notes.txt").to_str().unwrap().to_string();
+
+    let output = search(&["--archives", "--sort=path", "warning", &bad, &notes]);
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("{}:build/log.txt:warning: unused variable\n{}:warning: none\n", bad, notes)
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(&format!("{}: corrupt tar header at byte 3072: bad checksum", bad)));
+    assert!(stderr.contains("1 file(s) could not be searched"));
+}
+
+#[test]
+fn narrows_searches_with_an_index_without_changing_results() {
+    let dir = common::fixture_dir(
+        "index",
+        &[
+            ("poem.txt", include_str!("../poem.txt")),
+            ("notes/frogs.md", "# Frogs\nA frog in a bog.\n"),
+            ("notes/toads.md", "# Toads\nNo frogs here, only TOADS\n"),
+            ("data.bin", "frog\0toad\n"),
+        ],
+    );
+    let root = dir.to_str().unwrap();
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_minigrep_v2"))
+            .args(args)
+            .output()
+            .unwrap();
+        (output.status.success(), String::from_utf8(output.stdout).unwrap())
+    };
+    let searches: &[&[&str]] = &[
+        &["frog"],
+        &["-i", "toads"],
+        &["-cE", r"bog\b"],
+        &["-L", "Frogs"],
+        &["--json", "toad"],
+        &["-v", "frog"],
+        &["-E", "^#"],
+        &["newt"],
+    ];
+    let same_with_and_without_index = || {
+        for args in searches {
+            let plain = run(&[*args, &["--sort=path", root]].concat());
+            let indexed = run(&[*args, &["--sort=path", "--index", root]].concat());
+            assert_eq!(indexed, plain, "searching with {:?}", args);
+        }
+    };
+
+    // no index yet, so every file is searched
+    same_with_and_without_index();
+    assert_eq!(
+        run(&["index", root]),
+        (true, format!("{}: 4 new, 0 changed, 0 removed, 0 unchanged\n", root))
+    );
+    same_with_and_without_index();
+
+    // files that change aren't trusted until they're indexed again
+    fs::write(dir.join("notes/toads.md"), "# Toads\nA newt, and not one frog\n").unwrap();
+    same_with_and_without_index();
+    assert_eq!(
+        run(&["index", root]),
+        (true, format!("{}: 0 new, 1 changed, 0 removed, 3 unchanged\n", root))
+    );
+    same_with_and_without_index();
+    // nor does a corrupt one get in the way
+    fs::write(dir.join(".minigrep_v2-index"), "minigrep_v2 index 1\ngarbage").unwrap();
+    same_with_and_without_index();
+}
+
+#[test]
+#[cfg(unix)]
+fn follows_files_for_new_matches() {
+    let dir = common::fixture_dir("watch", &[("app.log", "ERROR before we started\n")]);
+    let log = dir.join("app.log");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_minigrep_v2"))
+        .args(["--watch", "-n", "ERROR", log.to_str().unwrap()])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let (sender, lines) = mpsc::channel();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    thread::spawn(move || {
+        for line in stdout.lines() {
+            if sender.send(line.unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+    let next = || lines.recv_timeout(Duration::from_secs(10)).unwrap();
+    let append = |text: &str| {
+        let mut file = fs::OpenOptions::new().append(true).open(&log).unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    };
+    // give it a moment to get to the end of what's there already
+    thread::sleep(Duration::from_millis(500));
+
+    append("ok\nERROR disk ");
+    append("full\nok\n");
+    assert_eq!(next(), "3:ERROR disk full");
+    // only the start of the file decides whether it's binary
+    append("bin\0ary\nERROR after a NUL\n");
+    assert_eq!(next(), "6:ERROR after a NUL");
+    // truncated, then written again from the top
+    fs::write(&log, "ERROR after truncation\n").unwrap();
+    assert_eq!(next(), "1:ERROR after truncation");
+    // rotated away, with one last line for the old file
+    append("ERROR on the way out\n");
+    fs::rename(&log, dir.join("app.log.1")).unwrap();
+    fs::write(&log, "ok\nERROR in the new file\n").unwrap();
+    assert_eq!(next(), "2:ERROR on the way out");
+    assert_eq!(next(), "2:ERROR in the new file");
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}